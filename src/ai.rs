@@ -0,0 +1,229 @@
+use crate::entities::{blocking_at, Entity};
+use crate::map::{distance, is_walkable, Map};
+use crate::status::{self, Status};
+use crate::stealth;
+use crate::ui::MessageLog;
+use quicksilver::geom::Vector;
+use rand::{rngs::StdRng, Rng};
+use slotmap::{DefaultKey, SlotMap};
+use std::collections::{HashMap, HashSet, VecDeque};
+
+pub const DIRECTIONS: [(i32, i32); 4] = [(1, 0), (-1, 0), (0, 1), (0, -1)];
+//Tiles a path search looks at before giving up
+const PATH_LIMIT: usize = 800;
+
+//Every hostile creature notices, chases, attacks or wanders for one turn
+//Returns the name of the last creature that hurt the player
+pub fn take_turns(
+    entities: &mut SlotMap<DefaultKey, Entity>,
+    map: &Map,
+    player_key: DefaultKey,
+    visibility: i32,
+    rng: &mut StdRng,
+    message_log: &mut MessageLog,
+) -> Option<&'static str> {
+    let mut attacker = None;
+    let hostile: Vec<DefaultKey> = entities
+        .iter()
+        .filter(|(_k, entity)| entity.hostile)
+        .map(|(k, _entity)| k)
+        .collect();
+
+    for key in hostile {
+        //The killing blow stays the last one
+        if entities[player_key].hp <= 0 {
+            break;
+        }
+        if take_turn(entities, map, key, player_key, visibility, rng, message_log) {
+            attacker = Some(entities[key].name);
+        }
+    }
+
+    attacker
+}
+
+//Returns true if the creature hurt the player
+fn take_turn(
+    entities: &mut SlotMap<DefaultKey, Entity>,
+    map: &Map,
+    key: DefaultKey,
+    player_key: DefaultKey,
+    visibility: i32,
+    rng: &mut StdRng,
+    message_log: &mut MessageLog,
+) -> bool {
+    let player_pos = entities[player_key].pos;
+    let creature = &mut entities[key];
+    let name = creature.name;
+
+    let distance = distance(creature.pos, player_pos);
+    if stealth::detects(visibility, distance, creature.perception) {
+        if !creature.alert {
+            message_log.push(&format!("The {} notices you!", name));
+        }
+        creature.alert = true;
+        creature.target = Some(player_pos);
+    } else {
+        creature.alert = false;
+    }
+
+    let pos = creature.pos;
+    let alert = creature.alert;
+    //The dog gets bitten when it's in reach and the player isn't
+    if !(alert && is_adjacent(pos, player_pos)) {
+        let dog = entities
+            .iter()
+            .find(|(_k, entity)| entity.ally && is_adjacent(entity.pos, pos))
+            .map(|(k, _entity)| k);
+        if let Some(dog) = dog {
+            let dog = &mut entities[dog];
+            dog.hp -= 1;
+            if dog.is_in_fov {
+                message_log.push(&format!("The {} bites your dog!", name));
+            }
+            return false;
+        }
+    }
+    match entities[key].target {
+        Some(_) if alert && is_adjacent(pos, player_pos) => {
+            let player = &mut entities[player_key];
+            player.hp -= 1;
+            message_log.push(&format!("The {} bites you!", name));
+            if rng.gen_range(0, 100) < status::BITE_POISON_PERC {
+                status::afflict(
+                    player,
+                    Status::Poisoned,
+                    status::BITE_POISON_TURNS,
+                    message_log,
+                );
+            }
+            return true;
+        }
+        Some(target) => {
+            step_towards(entities, map, key, target);
+            if entities[key].pos == target {
+                entities[key].target = None;
+            }
+        }
+        None => {
+            let roll = rng.gen_range(0, DIRECTIONS.len() * 2);
+            if let Some((dx, dy)) = DIRECTIONS.get(roll) {
+                step(entities, map, key, Vector::new(*dx, *dy));
+            }
+        }
+    }
+    false
+}
+
+//Creatures that hear a noise and aren't busy chasing the player go to look
+//Returns the ones that heard it
+pub fn hear(
+    entities: &mut SlotMap<DefaultKey, Entity>,
+    pos: Vector,
+    loudness: i32,
+) -> Vec<DefaultKey> {
+    entities
+        .iter_mut()
+        .filter(|(_k, entity)| entity.hostile && !entity.alert)
+        .filter(|(_k, entity)| distance(entity.pos, pos) <= loudness)
+        .map(|(k, entity)| {
+            entity.target = Some(pos);
+            k
+        })
+        .collect()
+}
+
+fn is_adjacent(a: Vector, b: Vector) -> bool {
+    (a.x - b.x).abs() + (a.y - b.y).abs() == 1.0
+}
+
+//Along the longer axis first, sideways if that's blocked
+fn step_towards(
+    entities: &mut SlotMap<DefaultKey, Entity>,
+    map: &Map,
+    key: DefaultKey,
+    target: Vector,
+) {
+    let delta = target - entities[key].pos;
+    let along_x = Vector::new(sign(delta.x), 0);
+    let along_y = Vector::new(0, sign(delta.y));
+    let (first, second) = if delta.x.abs() >= delta.y.abs() {
+        (along_x, along_y)
+    } else {
+        (along_y, along_x)
+    };
+
+    for direction in [first, second].iter() {
+        if *direction != Vector::ZERO && step(entities, map, key, *direction) {
+            return;
+        }
+    }
+}
+
+//One step along the shortest way around walls and creatures, returns false if there is none
+pub fn path_step(
+    entities: &mut SlotMap<DefaultKey, Entity>,
+    map: &Map,
+    key: DefaultKey,
+    goal: Vector,
+) -> bool {
+    let start = entities[key].pos;
+    let cell = |pos: Vector| (pos.x as i32, pos.y as i32);
+    let taken: HashSet<(i32, i32)> = entities
+        .values()
+        .filter(|entity| entity.blocks)
+        .map(|entity| cell(entity.pos))
+        .collect();
+    let mut came_from: HashMap<(i32, i32), Vector> = HashMap::new();
+    let mut frontier = VecDeque::new();
+    frontier.push_back(start);
+    came_from.insert(cell(start), start);
+
+    while let Some(pos) = frontier.pop_front() {
+        if pos == goal {
+            //Walk back to the tile next to the start
+            let mut step_to = pos;
+            while came_from[&cell(step_to)] != start {
+                step_to = came_from[&cell(step_to)];
+            }
+            return step(entities, map, key, step_to - start);
+        }
+        if came_from.len() > PATH_LIMIT {
+            break;
+        }
+        for (dx, dy) in DIRECTIONS.iter() {
+            let next = pos + Vector::new(*dx, *dy);
+            //The goal may be taken, by the player for one
+            let free = next == goal || !taken.contains(&cell(next));
+            if is_walkable(map, next) && free && !came_from.contains_key(&cell(next)) {
+                came_from.insert(cell(next), pos);
+                frontier.push_back(next);
+            }
+        }
+    }
+    false
+}
+
+fn sign(value: f32) -> i32 {
+    if value > 0.0 {
+        1
+    } else if value < 0.0 {
+        -1
+    } else {
+        0
+    }
+}
+
+fn step(
+    entities: &mut SlotMap<DefaultKey, Entity>,
+    map: &Map,
+    key: DefaultKey,
+    direction: Vector,
+) -> bool {
+    let future_pos = entities[key].pos + direction;
+    if is_walkable(map, future_pos) && blocking_at(entities, future_pos).is_none() {
+        entities[key].pos = future_pos;
+        return true;
+    }
+    false
+}
@@ -0,0 +1,186 @@
+use crate::camera::Camera;
+use crate::map::{distance, Map, Palette};
+use crate::render::Renderer;
+use crate::theme::Theme;
+use quicksilver::geom::Vector;
+use std::collections::VecDeque;
+
+//Frames each effect takes at normal speed, a frame is one update of the frontend
+const FRAMES_PER_CELL: u32 = 2;
+const FLASH_FRAMES: u32 = 8;
+const FRAMES_PER_RING: u32 = 3;
+
+pub enum Effect {
+    //A glyph moving along a path, e.g. a thrown pebble
+    Projectile { path: Vec<Vector>, glyph: char },
+    //A damaged entity, the glyph stays visible even if it died
+    Flash { pos: Vector, glyph: char },
+    //A ring growing from where a noise was made, up to how far it carries
+    Ripple { center: Vector, radius: i32 },
+}
+
+impl Effect {
+    fn duration(&self) -> u32 {
+        match self {
+            Effect::Projectile { path, .. } => path.len() as u32 * FRAMES_PER_CELL,
+            Effect::Flash { .. } => FLASH_FRAMES,
+            Effect::Ripple { radius, .. } => *radius as u32 * FRAMES_PER_RING,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Speed {
+    Normal,
+    Fast,
+    Off,
+}
+
+impl Speed {
+    pub fn from_name(name: &str) -> Option<Speed> {
+        match name {
+            "normal" => Some(Speed::Normal),
+            "fast" => Some(Speed::Fast),
+            "off" => Some(Speed::Off),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Speed::Normal => "normal",
+            Speed::Fast => "fast",
+            Speed::Off => "off",
+        }
+    }
+
+    fn frames_per_tick(self) -> u32 {
+        match self {
+            Speed::Normal => 1,
+            Speed::Fast => 3,
+            Speed::Off => 0,
+        }
+    }
+}
+
+//Effects play one after the other, the game never waits for them
+pub struct Animations {
+    queue: VecDeque<(Effect, u32)>,
+    pub speed: Speed,
+}
+
+impl Animations {
+    pub fn new() -> Animations {
+        Animations {
+            queue: VecDeque::new(),
+            speed: Speed::Normal,
+        }
+    }
+
+    pub fn push(&mut self, effect: Effect) {
+        if self.speed != Speed::Off && effect.duration() > 0 {
+            self.queue.push_back((effect, 0));
+        }
+    }
+
+    pub fn is_playing(&self) -> bool {
+        !self.queue.is_empty()
+    }
+
+    //Called once per frame by the frontend
+    pub fn tick(&mut self) {
+        if let Some((effect, frame)) = self.queue.front_mut() {
+            *frame += self.speed.frames_per_tick();
+            if *frame >= effect.duration() {
+                self.queue.pop_front();
+            }
+        }
+    }
+
+    //Drops whatever is left, so a new turn never shows effects from the last one
+    pub fn skip(&mut self) {
+        self.queue.clear();
+    }
+
+    pub fn switch_speed(&mut self) -> Speed {
+        self.speed = match self.speed {
+            Speed::Normal => Speed::Fast,
+            Speed::Fast => Speed::Off,
+            Speed::Off => Speed::Normal,
+        };
+        if self.speed == Speed::Off {
+            self.skip();
+        }
+        self.speed
+    }
+}
+
+impl Default for Animations {
+    fn default() -> Animations {
+        Animations::new()
+    }
+}
+
+pub fn draw_animations(
+    renderer: &mut dyn Renderer,
+    animations: &Animations,
+    map: &Map,
+    camera: &Camera,
+    theme: &Theme,
+) {
+    let (effect, frame) = match animations.queue.front() {
+        Some((effect, frame)) => (effect, *frame),
+        None => return,
+    };
+
+    match effect {
+        Effect::Projectile { path, glyph } => {
+            let index = (frame / FRAMES_PER_CELL) as usize;
+            if let Some(screen_pos) = path.get(index).and_then(|pos| camera.to_screen(*pos)) {
+                renderer.put_glyph(
+                    screen_pos,
+                    *glyph,
+                    theme.lit("pebble", Palette::WHITE),
+                    None,
+                );
+            }
+        }
+        Effect::Flash { pos, glyph } => {
+            if let Some(screen_pos) = camera.to_screen(*pos) {
+                renderer.put_glyph(
+                    screen_pos,
+                    *glyph,
+                    theme.text,
+                    Some(theme.lit("flash", Palette::RED)),
+                );
+            }
+        }
+        Effect::Ripple { center, .. } => {
+            //Known walls keep their glyph, everything else on the ring is drawn
+            let ring = (frame / FRAMES_PER_RING) as i32 + 1;
+            for dx in -ring..=ring {
+                for dy in -ring..=ring {
+                    let pos = *center + Vector::new(dx, dy);
+                    if distance(*center, pos) != ring || pos.x < 0.0 || pos.y < 0.0 {
+                        continue;
+                    }
+                    let known_wall = map
+                        .get(pos.x as usize)
+                        .and_then(|column| column.get(pos.y as usize))
+                        .map_or(true, |tile| tile.explored && tile.blocks_movement);
+                    if known_wall {
+                        continue;
+                    }
+                    if let Some(screen_pos) = camera.to_screen(pos) {
+                        renderer.put_glyph(
+                            screen_pos,
+                            '.',
+                            theme.lit("ripple", theme.border),
+                            None,
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,200 @@
+//Plays the game inside a terminal, e.g. over SSH
+use crossterm::{
+    cursor,
+    event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    execute, queue,
+    style::{Color as TermColor, Print, SetBackgroundColor, SetForegroundColor},
+    terminal,
+};
+use quicksilver::{geom::Vector, graphics::Color};
+use rl_2019::game::Game;
+use rl_2019::options::Options;
+use rl_2019::render::GridRenderer;
+use rl_2019::replay::{Action, Playback, Session};
+use rl_2019::scene::{self, Command, SceneStack};
+use rl_2019::score::HighScores;
+use rl_2019::theme::Themes;
+use std::io::{self, Write};
+use std::time::Duration;
+
+//Rows kept below the map for pebbles and the last few messages
+const PANEL_ROWS: u16 = 8;
+
+//Raw mode and the alternate screen, restored on drop even if the game panics
+struct RawTerminal;
+
+impl RawTerminal {
+    fn enter() -> io::Result<RawTerminal> {
+        terminal::enable_raw_mode()?;
+        execute!(io::stdout(), terminal::EnterAlternateScreen, cursor::Hide)?;
+        Ok(RawTerminal)
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        let _ = execute!(io::stdout(), cursor::Show, terminal::LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+fn main() -> io::Result<()> {
+    let options = Options::from_args();
+    let mut session = Session::start(&options)?;
+    let mut game = Game::new(session.seed);
+    game.themes = Themes::load(&options.themes);
+    game.animations.speed = options.animations;
+    let mut scenes = if session.playback.is_some() {
+        game.message_log.push("Replaying a recorded run.");
+        SceneStack::playing()
+    } else {
+        game.high_scores = HighScores::load();
+        game.morgue_dir = Some(options.morgue.clone());
+        SceneStack::title()
+    };
+
+    let _terminal = RawTerminal::enter()?;
+    let mut screen = fit_to_terminal(&mut game)?;
+    let mut dirty = true;
+
+    loop {
+        if dirty {
+            draw(&game, &scenes, screen)?;
+            dirty = false;
+        }
+
+        let mut action = None;
+        if event::poll(Duration::from_millis(16))? {
+            match event::read()? {
+                Event::Key(key) if key.kind != KeyEventKind::Release => {
+                    dirty = true;
+                    if key.code == KeyCode::Char('c')
+                        && key.modifiers.contains(KeyModifiers::CONTROL)
+                    {
+                        break;
+                    }
+                    match &mut session.playback {
+                        Some(playback) => {
+                            if key.code == KeyCode::Char('q') {
+                                break;
+                            }
+                            action = playback_input(key, playback);
+                            if let Some(key) = to_scene_key(key) {
+                                scenes.handle_global(key, &mut game);
+                            }
+                        }
+                        None => {
+                            match to_scene_key(key).and_then(|key| scenes.handle(key, &mut game)) {
+                                Some(Command::Act(live_action)) => action = Some(live_action),
+                                Some(Command::NewGame(seed)) => {
+                                    session.record_run(seed);
+                                    game.new_run(seed);
+                                }
+                                Some(Command::Quit) => break,
+                                None => {}
+                            }
+                        }
+                    }
+                }
+                Event::Resize(..) => {
+                    screen = fit_to_terminal(&mut game)?;
+                    dirty = true;
+                }
+                _ => {}
+            }
+        }
+
+        if game.animations.is_playing() {
+            game.animations.tick();
+            dirty = true;
+        }
+
+        if let Some(playback) = &mut session.playback {
+            if action.is_none() {
+                action = playback.tick();
+            }
+            if playback.just_finished() {
+                game.message_log.push("The replay is over.");
+                dirty = true;
+            }
+        }
+
+        if let Some(action) = action {
+            session.commit(action);
+            game.apply(action);
+            dirty = true;
+        }
+        scenes.update(&game);
+    }
+
+    Ok(())
+}
+
+//Map window takes whatever the panel and the right border leave free
+fn fit_to_terminal(game: &mut Game) -> io::Result<Vector> {
+    let (cols, rows) = terminal::size()?;
+    let viewport = Vector::new(
+        cols.saturating_sub(2).max(1),
+        rows.saturating_sub(PANEL_ROWS).max(1),
+    );
+    game.set_viewport(viewport);
+    Ok(Vector::new(cols, rows))
+}
+
+fn draw(game: &Game, scenes: &SceneStack, screen: Vector) -> io::Result<()> {
+    let mut grid = GridRenderer::new(screen);
+    scenes.draw(game, &mut grid, screen);
+
+    let mut out = io::stdout().lock();
+    let background = game.themes.current().background;
+    let mut colors = None;
+    for (y, row) in grid.cells.iter().enumerate() {
+        queue!(out, cursor::MoveTo(0, y as u16))?;
+        for cell in row.iter() {
+            let cell_colors = (cell.fg, cell.bg.unwrap_or(background));
+            if colors != Some(cell_colors) {
+                queue!(
+                    out,
+                    SetForegroundColor(to_ansi(cell_colors.0)),
+                    SetBackgroundColor(to_ansi(cell_colors.1))
+                )?;
+                colors = Some(cell_colors);
+            }
+            queue!(out, Print(cell.glyph))?;
+        }
+    }
+    out.flush()
+}
+
+fn to_ansi(color: Color) -> TermColor {
+    let channel = |value: f32| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+    TermColor::Rgb {
+        r: channel(color.r),
+        g: channel(color.g),
+        b: channel(color.b),
+    }
+}
+
+fn to_scene_key(key: KeyEvent) -> Option<scene::Key> {
+    match key.code {
+        KeyCode::Up => Some(scene::Key::Up),
+        KeyCode::Down => Some(scene::Key::Down),
+        KeyCode::Left => Some(scene::Key::Left),
+        KeyCode::Right => Some(scene::Key::Right),
+        KeyCode::Enter => Some(scene::Key::Enter),
+        KeyCode::Esc => Some(scene::Key::Escape),
+        KeyCode::Char(c) => Some(scene::Key::Char(c.to_ascii_lowercase())),
+        _ => None,
+    }
+}
+
+fn playback_input(key: KeyEvent, playback: &mut Playback) -> Option<Action> {
+    match key.code {
+        KeyCode::Char(' ') => playback.paused = !playback.paused,
+        KeyCode::Char('+') | KeyCode::Char('=') => playback.faster(),
+        KeyCode::Char('-') => playback.slower(),
+        KeyCode::Char('.') if playback.paused => return playback.step(),
+        _ => {}
+    }
+    None
+}
@@ -0,0 +1,43 @@
+use crate::map::{map_size, Map, VIEWPORT_SIZE};
+use quicksilver::geom::Vector;
+
+//Top-left map cell of the visible window, kept centred on whatever it follows
+pub struct Camera {
+    pub offset: Vector,
+    pub size: Vector,
+}
+
+impl Camera {
+    pub fn new() -> Camera {
+        Camera {
+            offset: Vector::ZERO,
+            size: VIEWPORT_SIZE,
+        }
+    }
+
+    pub fn follow(&mut self, target: Vector, map: &Map) {
+        let size = map_size(map);
+        let max_x = (size.x - self.size.x).max(0.0);
+        let max_y = (size.y - self.size.y).max(0.0);
+
+        let x = target.x - (self.size.x / 2.0).floor();
+        let y = target.y - (self.size.y / 2.0).floor();
+        self.offset = Vector::new(x.max(0.0).min(max_x), y.max(0.0).min(max_y));
+    }
+
+    //Map position to viewport cell, None when it's outside the window
+    pub fn to_screen(&self, pos: Vector) -> Option<Vector> {
+        let screen = pos - self.offset;
+        if screen.x >= 0.0 && screen.y >= 0.0 && screen.x < self.size.x && screen.y < self.size.y {
+            Some(screen)
+        } else {
+            None
+        }
+    }
+}
+
+impl Default for Camera {
+    fn default() -> Camera {
+        Camera::new()
+    }
+}
@@ -0,0 +1,258 @@
+//The player's cave dog: it follows or stays, fetches thrown pebbles and barks at what it hears
+use crate::ai::{self, DIRECTIONS};
+use crate::entities::{blocking_at, Entity};
+use crate::map::{distance, is_walkable, Map};
+use crate::ui::MessageLog;
+use quicksilver::geom::Vector;
+use slotmap::{DefaultKey, SlotMap};
+
+//How close the dog keeps to the player while following
+const FOLLOW_DISTANCE: i32 = 2;
+//The dog only comes along to another level from this close
+const LEASH: i32 = 3;
+//How far the dog hears creatures moving in the dark
+const HEARING_RANGE: i32 = 6;
+//How loud a bark is, creatures within it come to look too
+pub const BARK_NOISE: i32 = 5;
+
+pub struct Companion {
+    pub key: DefaultKey,
+    pub staying: bool,
+    //Pebbles in its mouth, dropped at the player's feet
+    pub carrying: u32,
+    //The thrown pebble it's running after
+    fetching: Option<DefaultKey>,
+    //Creatures it already barked at
+    heard: Vec<DefaultKey>,
+}
+
+impl Companion {
+    //Next to the given tile, none if there is no room
+    pub fn place(
+        entities: &mut SlotMap<DefaultKey, Entity>,
+        map: &Map,
+        near: Vector,
+    ) -> Option<Companion> {
+        let pos = free_spot(entities, map, near)?;
+        Some(Companion {
+            key: entities.insert_with_key(|k| Entity::new_dog(k, pos)),
+            staying: false,
+            carrying: 0,
+            fetching: None,
+            heard: vec![],
+        })
+    }
+
+    //Brings the dog onto a new level next to the player, returns false if there is no room
+    pub fn follow_player(
+        &mut self,
+        dog: Entity,
+        entities: &mut SlotMap<DefaultKey, Entity>,
+        map: &Map,
+        near: Vector,
+    ) -> bool {
+        let pos = match free_spot(entities, map, near) {
+            Some(pos) => pos,
+            None => return false,
+        };
+        self.key = entities.insert_with_key(|k| {
+            let mut dog = dog;
+            dog.key = k;
+            dog.pos = pos;
+            dog
+        });
+        self.fetching = None;
+        self.heard.clear();
+        true
+    }
+
+    //A dog told to stay, or left too far behind, doesn't follow the player off the level
+    pub fn comes_along(&self, entities: &SlotMap<DefaultKey, Entity>, player_pos: Vector) -> bool {
+        !self.staying && distance(entities[self.key].pos, player_pos) <= LEASH
+    }
+
+    //Stay drops whatever it was fetching, follow brings it back
+    pub fn toggle_stay(&mut self) {
+        self.staying = !self.staying;
+        self.fetching = None;
+    }
+
+    pub fn fetch(&mut self, pebble: DefaultKey) {
+        if !self.staying {
+            self.fetching = Some(pebble);
+        }
+    }
+
+    //Returns true if it barked at a creature it just heard
+    pub fn take_turn(
+        &mut self,
+        entities: &mut SlotMap<DefaultKey, Entity>,
+        map: &Map,
+        player_key: DefaultKey,
+        message_log: &mut MessageLog,
+    ) -> bool {
+        let player_pos = entities[player_key].pos;
+        let pos = entities[self.key].pos;
+
+        //Only creatures out of the player's sight are worth a bark
+        let heard: Vec<DefaultKey> = entities
+            .iter()
+            .filter(|(_k, entity)| entity.hostile && distance(entity.pos, pos) <= HEARING_RANGE)
+            .map(|(k, _entity)| k)
+            .collect();
+        let barks = heard
+            .iter()
+            .any(|k| !self.heard.contains(k) && !entities[*k].is_in_fov);
+        self.heard = heard;
+
+        if let Some(pebble) = self.fetching {
+            let pebble_pos = entities
+                .get(pebble)
+                .filter(|entity| entity.name == "pebble")
+                .map(|entity| entity.pos);
+            match pebble_pos {
+                Some(pebble_pos)
+                    if pebble_pos == pos || ai::path_step(entities, map, self.key, pebble_pos) =>
+                {
+                    if entities[self.key].pos == pebble_pos {
+                        entities.remove(pebble);
+                        self.carrying += 1;
+                        self.fetching = None;
+                        if entities[self.key].is_in_fov {
+                            message_log.push("Your dog picks up the pebble.");
+                        }
+                    }
+                }
+                //Gone, or out of reach
+                _ => self.fetching = None,
+            }
+        } else if self.carrying > 0 && distance(pos, player_pos) <= 1 {
+            for _ in 0..self.carrying {
+                entities.insert_with_key(|k| Entity::new_thrown_pebble(k, player_pos));
+            }
+            message_log.push(if self.carrying == 1 {
+                "Your dog drops the pebble at your feet."
+            } else {
+                "Your dog drops the pebbles at your feet."
+            });
+            self.carrying = 0;
+        } else if !self.staying
+            && (self.carrying > 0 || distance(pos, player_pos) > FOLLOW_DISTANCE)
+        {
+            ai::path_step(entities, map, self.key, player_pos);
+        }
+
+        barks
+    }
+}
+
+fn free_spot(entities: &SlotMap<DefaultKey, Entity>, map: &Map, near: Vector) -> Option<Vector> {
+    DIRECTIONS
+        .iter()
+        .map(|(dx, dy)| near + Vector::new(*dx, *dy))
+        .find(|pos| is_walkable(map, *pos) && blocking_at(entities, *pos).is_none())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::Tile;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    fn open_map() -> Map {
+        vec![vec![Tile::from_glyph('.'); 16]; 16]
+    }
+
+    //The player and the dog, with nothing else around
+    fn setup(
+        player_pos: Vector,
+        dog_pos: Vector,
+    ) -> (SlotMap<DefaultKey, Entity>, DefaultKey, Companion) {
+        let mut entities = SlotMap::new();
+        let player_key = entities.insert_with_key(|k| Entity::new_player(k, player_pos));
+        let companion = Companion {
+            key: entities.insert_with_key(|k| Entity::new_dog(k, dog_pos)),
+            staying: false,
+            carrying: 0,
+            fetching: None,
+            heard: vec![],
+        };
+        (entities, player_key, companion)
+    }
+
+    #[test]
+    fn catches_up_with_the_player() {
+        let map = open_map();
+        let (mut entities, player_key, mut companion) =
+            setup(Vector::new(1, 1), Vector::new(10, 10));
+        let mut log = MessageLog::new();
+        for _ in 0..20 {
+            companion.take_turn(&mut entities, &map, player_key, &mut log);
+        }
+        let dog_pos = entities[companion.key].pos;
+        assert!(distance(dog_pos, entities[player_key].pos) <= FOLLOW_DISTANCE);
+    }
+
+    #[test]
+    fn staying_dog_is_left_behind() {
+        let map = open_map();
+        let (mut entities, player_key, mut companion) = setup(Vector::new(1, 1), Vector::new(2, 1));
+        assert!(companion.comes_along(&entities, Vector::new(1, 1)));
+        companion.toggle_stay();
+        entities[player_key].pos = Vector::new(10, 1);
+        companion.take_turn(&mut entities, &map, player_key, &mut MessageLog::new());
+        assert_eq!(entities[companion.key].pos, Vector::new(2, 1));
+        assert!(!companion.comes_along(&entities, Vector::new(1, 1)));
+        companion.toggle_stay();
+        assert!(!companion.comes_along(&entities, Vector::new(10, 1)));
+    }
+
+    #[test]
+    fn fetches_a_thrown_pebble_back() {
+        let map = open_map();
+        let player_pos = Vector::new(1, 1);
+        let (mut entities, player_key, mut companion) = setup(player_pos, Vector::new(2, 1));
+        let pebble = entities.insert_with_key(|k| Entity::new_thrown_pebble(k, Vector::new(9, 4)));
+        companion.fetch(pebble);
+        let mut log = MessageLog::new();
+        for _ in 0..30 {
+            companion.take_turn(&mut entities, &map, player_key, &mut log);
+        }
+        assert!(!entities.contains_key(pebble));
+        assert_eq!(companion.carrying, 0);
+        let at_feet = entities
+            .values()
+            .filter(|entity| entity.name == "pebble" && entity.pos == player_pos)
+            .count();
+        assert_eq!(at_feet, 1);
+    }
+
+    #[test]
+    fn barks_once_at_each_unseen_creature() {
+        let map = open_map();
+        let (mut entities, player_key, mut companion) = setup(Vector::new(1, 1), Vector::new(2, 1));
+        entities.insert_with_key(|k| Entity::spawn("crawler", k, Vector::new(6, 1)).unwrap());
+        let mut log = MessageLog::new();
+        assert!(companion.take_turn(&mut entities, &map, player_key, &mut log));
+        assert!(!companion.take_turn(&mut entities, &map, player_key, &mut log));
+    }
+
+    #[test]
+    fn creatures_bite_the_dog() {
+        let map = open_map();
+        let (mut entities, player_key, companion) = setup(Vector::new(1, 1), Vector::new(12, 12));
+        entities.insert_with_key(|k| Entity::spawn("crawler", k, Vector::new(12, 13)).unwrap());
+        let (dog_hp, player_hp) = (entities[companion.key].hp, entities[player_key].hp);
+        let mut rng = StdRng::seed_from_u64(1);
+        ai::take_turns(
+            &mut entities,
+            &map,
+            player_key,
+            0,
+            &mut rng,
+            &mut MessageLog::new(),
+        );
+        assert_eq!(entities[companion.key].hp, dog_hp - 1);
+        assert_eq!(entities[player_key].hp, player_hp);
+    }
+}
@@ -0,0 +1,11 @@
+//Errors for the plain-text data files: replays, vaults, spawn tables, themes and sprite sheets
+use std::io;
+
+pub fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+//"Invalid <kind> line: <line>", the line as it was read
+pub fn invalid_line(kind: &str, line: &str) -> io::Error {
+    invalid(&format!("Invalid {} line: {}", kind, line))
+}
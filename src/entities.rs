@@ -1,17 +1,21 @@
-use crate::map::{get_line, Map, Palette, TILE_SIZE};
+use crate::camera::Camera;
+use crate::map::{self, distance, Map, Palette};
+use crate::render::Renderer;
+use crate::spawn::{self, SpawnTable};
+use crate::status::Statuses;
+use crate::theme::Theme;
+use crate::torch;
 use crate::ui::{MessageLog, UIData};
-use quicksilver::{
-    geom::{Rectangle, Transform, Vector},
-    graphics::{Background::*, Color, Image},
-    lifecycle::Window,
-};
+use quicksilver::{geom::Vector, graphics::Color};
+use rand::{rngs::StdRng, Rng};
 use slotmap::{DefaultKey, SlotMap};
-use std::collections::HashMap;
 
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Entity {
     pub key: DefaultKey,
     pub name: &'static str,
+    //Shown by the look mode
+    pub description: &'static str,
     pub glyph: char,
     pub color: Color,
     pub hp: i32,
@@ -19,15 +23,43 @@ pub struct Entity {
     pub is_in_fov: bool,
     pub color_in_fov: Color,
     pub pos: Vector,
+    pub blocks: bool,
+    pub hostile: bool,
+    //On the player's side, creatures and thrown pebbles leave it alone
+    pub ally: bool,
+    //Visibility the player needs, after distance, for this creature to notice them
+    pub perception: i32,
+    pub alert: bool,
+    pub target: Option<Vector>,
+    pub statuses: Statuses,
     pickable: bool,
-    z: i32,
+    //Picked up before, so it's worth nothing the second time
+    pub collected: bool,
+    pub z: i32,
 }
 
 impl Entity {
-    fn new_pebble(key: DefaultKey, pos: Vector) -> Entity {
+    //Builds the entity a spawn table id stands for
+    pub fn spawn(id: &str, key: DefaultKey, pos: Vector) -> Option<Entity> {
+        match id {
+            "pebble" => Some(Entity::new_pebble(key, pos)),
+            "oil" => Some(Entity::new_oil(key, pos)),
+            "crawler" => Some(Entity::new_crawler(key, pos)),
+            _ => None,
+        }
+    }
+
+    //One the player owned before, thrown or brought back by the dog
+    pub fn new_thrown_pebble(key: DefaultKey, pos: Vector) -> Entity {
+        let mut pebble = Entity::new_pebble(key, pos);
+        pebble.collected = true;
+        pebble
+    }
+    pub fn new_pebble(key: DefaultKey, pos: Vector) -> Entity {
         Entity {
             key,
             name: "pebble",
+            description: "A smooth pebble, just right for throwing.",
             glyph: '.',
             color: Palette::DARK_BLUE,
             hp: 0,
@@ -35,7 +67,63 @@ impl Entity {
             is_in_fov: false,
             color_in_fov: Palette::WHITE,
             pos,
+            blocks: false,
+            hostile: false,
+            ally: false,
+            perception: 0,
+            alert: false,
+            target: None,
+            statuses: Statuses::new(),
+            pickable: true,
+            collected: false,
+            z: 1,
+        }
+    }
+    fn new_oil(key: DefaultKey, pos: Vector) -> Entity {
+        Entity {
+            key,
+            name: "oil",
+            description: "A flask of oil for your torch.",
+            glyph: '!',
+            color: Palette::DARK_BLUE,
+            hp: 0,
+            max_hp: 0,
+            is_in_fov: false,
+            color_in_fov: Palette::ORANGE,
+            pos,
+            blocks: false,
+            hostile: false,
+            ally: false,
+            perception: 0,
+            alert: false,
+            target: None,
+            statuses: Statuses::new(),
+            pickable: true,
+            collected: false,
+            z: 1,
+        }
+    }
+    pub fn new_artifact(key: DefaultKey, pos: Vector) -> Entity {
+        Entity {
+            key,
+            name: "artifact",
+            description: "What you came down here for. Bring it back to the surface.",
+            glyph: '&',
+            color: Palette::DARK_BLUE,
+            hp: 0,
+            max_hp: 0,
+            is_in_fov: false,
+            color_in_fov: Palette::YELLOW,
+            pos,
+            blocks: false,
+            hostile: false,
+            ally: false,
+            perception: 0,
+            alert: false,
+            target: None,
+            statuses: Statuses::new(),
             pickable: true,
+            collected: false,
             z: 1,
         }
     }
@@ -43,6 +131,7 @@ impl Entity {
         Entity {
             key,
             name: "crosshair",
+            description: "",
             glyph: 'x',
             color: Palette::WHITE,
             hp: 0,
@@ -50,14 +139,71 @@ impl Entity {
             is_in_fov: true,
             color_in_fov: Palette::WHITE,
             pos,
+            blocks: false,
+            hostile: false,
+            ally: false,
+            perception: 0,
+            alert: false,
+            target: None,
+            statuses: Statuses::new(),
             pickable: false,
+            collected: false,
             z: 10,
         }
     }
+    fn new_crawler(key: DefaultKey, pos: Vector) -> Entity {
+        Entity {
+            key,
+            name: "crawler",
+            description: "A blind crawler, it hunts by light and sound.",
+            glyph: 'c',
+            color: Palette::DARK_BLUE,
+            hp: 2,
+            max_hp: 2,
+            is_in_fov: false,
+            color_in_fov: Palette::RED,
+            pos,
+            blocks: true,
+            hostile: true,
+            ally: false,
+            perception: 0,
+            alert: false,
+            target: None,
+            statuses: Statuses::new(),
+            pickable: false,
+            collected: false,
+            z: 2,
+        }
+    }
+    pub fn new_dog(key: DefaultKey, pos: Vector) -> Entity {
+        Entity {
+            key,
+            name: "dog",
+            description: "Your cave dog, it fetches pebbles and barks at what it hears.",
+            glyph: 'd',
+            color: Palette::DARK_BLUE,
+            hp: 3,
+            max_hp: 3,
+            is_in_fov: false,
+            color_in_fov: Palette::PEACH,
+            pos,
+            blocks: true,
+            hostile: false,
+            ally: true,
+            perception: 0,
+            alert: false,
+            target: None,
+            statuses: Statuses::new(),
+            pickable: false,
+            collected: false,
+            z: 2,
+        }
+    }
     pub fn new_player(key: DefaultKey, pos: Vector) -> Entity {
         Entity {
             key,
             name: "player",
+            description: "That's you, with pockets for pebbles.",
             glyph: '@',
             color: Palette::WHITE,
             hp: 3,
@@ -65,32 +211,70 @@ impl Entity {
             is_in_fov: true,
             color_in_fov: Palette::WHITE,
             pos,
+            blocks: true,
+            hostile: false,
+            ally: false,
+            perception: 0,
+            alert: false,
+            target: None,
+            statuses: Statuses::new(),
             pickable: false,
+            collected: false,
             z: 2,
         }
     }
 }
 
-const PEBBLE_PERC: u32 = 7;
+//Nothing spawns this close to the player, when the level is made or later
+const SAFE_DISTANCE: i32 = 10;
+//Random tiles tried before a group or one of its members is given up on
+const PLACEMENT_TRIES: u32 = 20;
 
-pub fn generate(map: &Map) -> SlotMap<DefaultKey, Entity> {
-    use rand::distributions::Uniform;
-    use rand::prelude::*;
+pub fn generate(
+    tables: &[SpawnTable],
+    map: &Map,
+    player_spawn: Vector,
+    depth: u32,
+    rng: &mut StdRng,
+) -> SlotMap<DefaultKey, Entity> {
+    let mut entities: SlotMap<DefaultKey, Entity> = SlotMap::new();
 
-    let mut rng = thread_rng();
-    let die_range = Uniform::new_inclusive(1, 100);
+    let table = match spawn::table_for(tables, depth) {
+        Some(table) => table,
+        None => return entities,
+    };
+    let width = map.len() as i32;
+    let height = map[0].len() as i32;
 
-    let mut entities: SlotMap<DefaultKey, Entity> = SlotMap::new();
+    let is_free = |entities: &SlotMap<DefaultKey, Entity>, pos: Vector| {
+        can_spawn_on(map, pos)
+            && distance(pos, player_spawn) > SAFE_DISTANCE
+            && entities.values().all(|entity| entity.pos != pos)
+    };
 
-    for x in 0..map.len() {
-        for y in 0..map[x].len() {
-            if !map[x][y].blocks {
-                let roll = die_range.sample(&mut rng);
-                if roll <= PEBBLE_PERC {
-                    entities.insert_with_key(|k| {
-                        Entity::new_pebble(k, Vector::new(x as i32, y as i32))
-                    });
-                }
+    for _ in 0..table.groups {
+        let entry = table.pick(rng, |_entry| true).unwrap();
+
+        let center = (0..PLACEMENT_TRIES)
+            .map(|_| Vector::new(rng.gen_range(0, width), rng.gen_range(0, height)))
+            .find(|pos| is_free(&entities, *pos));
+        let center = match center {
+            Some(center) => center,
+            None => continue,
+        };
+
+        //The rest of the group gathers around the first member
+        let size = rng.gen_range(entry.min_group, entry.max_group + 1);
+        for member in 0..size {
+            let pos = if member == 0 {
+                Some(center)
+            } else {
+                (0..PLACEMENT_TRIES)
+                    .map(|_| center + Vector::new(rng.gen_range(-1, 2), rng.gen_range(-1, 2)))
+                    .find(|pos| is_free(&entities, *pos))
+            };
+            if let Some(pos) = pos {
+                entities.insert_with_key(|k| Entity::spawn(&entry.id, k, pos).unwrap());
             }
         }
     }
@@ -98,69 +282,150 @@ pub fn generate(map: &Map) -> SlotMap<DefaultKey, Entity> {
     entities
 }
 
+//Ground a spawn can stand on: not a wall, not water and not a chasm
+fn can_spawn_on(map: &Map, pos: Vector) -> bool {
+    if pos.x < 0. || pos.y < 0. || pos.x >= map.len() as f32 || pos.y >= map[0].len() as f32 {
+        return false;
+    }
+    let tile = &map[pos.x as usize][pos.y as usize];
+    !tile.blocks_movement && !tile.swallows()
+}
+
+//One creature from the depth's table, somewhere the player can't see and far from them
+pub fn spawn_wanderer(
+    tables: &[SpawnTable],
+    entities: &mut SlotMap<DefaultKey, Entity>,
+    map: &Map,
+    player_pos: Vector,
+    depth: u32,
+    rng: &mut StdRng,
+) -> Option<DefaultKey> {
+    let table = spawn::table_for(tables, depth)?;
+    let entry = table.pick(rng, |entry| {
+        Entity::spawn(&entry.id, DefaultKey::default(), player_pos)
+            .is_some_and(|entity| entity.hostile)
+    })?;
+
+    let width = map.len() as i32;
+    let height = map[0].len() as i32;
+    let pos = (0..PLACEMENT_TRIES)
+        .map(|_| Vector::new(rng.gen_range(0, width), rng.gen_range(0, height)))
+        .find(|pos| {
+            can_spawn_on(map, *pos)
+                && !map[pos.x as usize][pos.y as usize].visible
+                && distance(*pos, player_pos) > SAFE_DISTANCE
+                && blocking_at(entities, *pos).is_none()
+        })?;
+    Some(entities.insert_with_key(|k| Entity::spawn(&entry.id, k, pos).unwrap()))
+}
+
+//Creature or player standing on a tile
+pub fn blocking_at(entities: &SlotMap<DefaultKey, Entity>, pos: Vector) -> Option<DefaultKey> {
+    entities
+        .iter()
+        .find(|(_k, entity)| entity.blocks && entity.pos == pos)
+        .map(|(k, _entity)| k)
+}
+
 pub fn draw_entities(
-    window: &mut Window,
+    renderer: &mut dyn Renderer,
     entities: &SlotMap<DefaultKey, Entity>,
-    tileset: &mut HashMap<char, Image>,
+    camera: &Camera,
+    theme: &Theme,
 ) {
-    entities.iter().for_each(|(_k, entity)| {
-        let image = tileset.get(&entity.glyph).unwrap();
-        if entity.name == "crosshair" {
-            window.draw_ex(
-                &Rectangle::new(entity.pos.times(TILE_SIZE), image.area().size()),
-                Col(Palette::DARK_BLUE),
-                Transform::IDENTITY,
-                entity.z - 1
-            )
-        }
-        window.draw_ex(
-            &Rectangle::new(entity.pos.times(TILE_SIZE), image.area().size()),
-            Blended(
-                &image,
-                if entity.is_in_fov {
-                    entity.color_in_fov
-                } else {
-                    entity.color
-                },
-            ),
-            Transform::IDENTITY,
-            entity.z,
-        )
-    });
+    let mut visible: Vec<(Vector, &Entity)> = entities
+        .iter()
+        .filter_map(|(_k, entity)| camera.to_screen(entity.pos).map(|pos| (pos, entity)))
+        .collect();
+    visible.sort_by_key(|(_pos, entity)| entity.z);
+
+    for (screen_pos, entity) in visible {
+        renderer.put_tile(
+            screen_pos,
+            entity.name,
+            entity.glyph,
+            if entity.is_in_fov {
+                theme.lit(entity.name, entity.color_in_fov)
+            } else {
+                theme.unlit(entity.name, entity.color)
+            },
+            if entity.name == "crosshair" {
+                Some(theme.background)
+            } else {
+                None
+            },
+        );
+    }
 }
 
-pub fn compute_fov(entities: &mut SlotMap<DefaultKey, Entity>, player_pos: Vector) {
+pub fn compute_fov(
+    entities: &mut SlotMap<DefaultKey, Entity>,
+    map: &Map,
+    player_pos: Vector,
+    radius: i32,
+) {
     entities.iter_mut().for_each(|(_k, entity)| {
         if entity.name != "crosshair" {
-            entity.is_in_fov = is_in_range(entity.pos, player_pos);
+            entity.is_in_fov = map::is_visible(map, player_pos, entity.pos, radius);
         } else {
             entity.is_in_fov = true;
         }
     });
 }
 
-fn is_in_range(from: Vector, to: Vector) -> bool {
-    get_line(from, to).len() <= 2
+pub fn health(entity: &Entity) -> &'static str {
+    if entity.hp >= entity.max_hp {
+        "unhurt"
+    } else if entity.hp * 2 > entity.max_hp {
+        "wounded"
+    } else {
+        "badly hurt"
+    }
 }
 
+//Returns how many of the items were picked up for the first time, those are the ones worth experience
 pub fn pickup(
     entities: &mut SlotMap<DefaultKey, Entity>,
     player_pos: Vector,
     ui_data: &mut UIData,
     message_log: &mut MessageLog,
-) {
+) -> u32 {
     let mut to_pickup = 0;
+    let mut fresh_pebbles = 0;
+    let mut flasks = 0;
+    let mut artifacts = 0;
     entities.retain(|_k, entity| {
         if entity.pos == player_pos && entity.pickable {
-            to_pickup += 1;
+            match entity.name {
+                "oil" => flasks += 1,
+                "artifact" => artifacts += 1,
+                _ => {
+                    to_pickup += 1;
+                    if !entity.collected {
+                        fresh_pebbles += 1;
+                    }
+                }
+            }
             false
         } else {
             true
         }
     });
     if to_pickup > 0 {
-        message_log.push("pickup");
+        message_log.push("You pickup a pebble");
+    }
+    for _ in 0..flasks {
+        ui_data.fuel = torch::refill(ui_data.fuel);
+    }
+    if flasks > 0 {
+        message_log.push("You refill your torch.");
+    }
+    if artifacts > 0 {
+        ui_data.has_artifact = true;
+        message_log.push("You take the artifact! Now find your way back up.");
     }
 
     ui_data.pebbles += to_pickup;
+    ui_data.pebbles_collected += fresh_pebbles;
+    fresh_pebbles + flasks + artifacts
 }
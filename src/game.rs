@@ -0,0 +1,1135 @@
+use crate::ai;
+use crate::animation::{self, Animations, Effect};
+use crate::camera::Camera;
+use crate::companion::{self, Companion};
+use crate::entities::{self, Entity};
+use crate::map::{self, distance, get_line, Map, Tile};
+use crate::morgue;
+use crate::progress::{self, Perk};
+use crate::render::Renderer;
+use crate::replay::Action;
+use crate::score::{HighScores, RunRecord};
+use crate::spawn::{self, SpawnTable};
+use crate::status::{self, Status};
+use crate::stealth;
+use crate::theme::Themes;
+use crate::torch;
+use crate::trap::{self, Trap};
+use crate::ui::{self, MessageLog, UIData};
+use quicksilver::geom::Vector;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use slotmap::{DefaultKey, SlotMap};
+use std::path::PathBuf;
+
+//How far from the player the crosshair can go
+pub const THROW_RANGE: i32 = 8;
+//Turns before a pebble dropped into a chasm is heard hitting the bottom
+const ECHO_DELAY: u32 = 3;
+//How much of the cave the clatter of a landed pebble shows
+const SOUNDING_RADIUS: i32 = 4;
+//Hp lost falling through a chasm
+const FALL_DAMAGE: i32 = 1;
+//Unrest it takes for a new creature to wander onto the level
+const UNREST_TO_SPAWN: u32 = 400;
+//Each time this many turns pass on a level, every turn stirs up one more unrest
+const LINGER_TURNS: u32 = 100;
+
+//The whole run, independent of the window or terminal it's shown in
+pub struct Game {
+    pub seed: u64,
+    rng: StdRng,
+    pub map: Map,
+    pub camera: Camera,
+    pub entities: SlotMap<DefaultKey, Entity>,
+    pub player_key: DefaultKey,
+    crosshair_key: Option<DefaultKey>,
+    //The crosshair is only a cursor for the look mode, not for aiming
+    looking: bool,
+    pub ui_data: UIData,
+    pub message_log: MessageLog,
+    pub high_scores: HighScores,
+    //Where morgue files go on death, none for replays and tests
+    pub morgue_dir: Option<PathBuf>,
+    pub themes: Themes,
+    pub animations: Animations,
+    //Name of the last creature, fall or trap that hurt the player
+    killer: Option<&'static str>,
+    //Pebbles falling down chasms, with the turns left until they land
+    echoes: Vec<(Vector, u32)>,
+    //Where the last pebble landed and the creatures that heard it, shown until the next turn
+    sounding: Option<(Vector, Vec<DefaultKey>)>,
+    //Turns spent on this level, and the lingering and noise building up to the next spawn
+    turns_on_level: u32,
+    unrest: u32,
+    //None once the dog is left behind
+    companion: Option<Companion>,
+    //Made it out of the cave with the artifact
+    escaped: bool,
+    //The run is in the high scores, dead or escaped
+    recorded: bool,
+    //Parsed once when the game starts, then kept from run to run
+    spawn_tables: Vec<SpawnTable>,
+}
+
+impl Game {
+    pub fn new(seed: u64) -> Game {
+        Game::start(seed, spawn::bundled())
+    }
+
+    fn start(seed: u64, spawn_tables: Vec<SpawnTable>) -> Game {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let (map, player_spawn, _stairs) = generate_level(1, &mut rng);
+        let mut entities = entities::generate(&spawn_tables, &map, player_spawn, 1, &mut rng);
+
+        let player_key = entities.insert_with_key(|k| Entity::new_player(k, player_spawn));
+        let companion = Companion::place(&mut entities, &map, player_spawn);
+        let mut camera = Camera::new();
+        camera.follow(player_spawn, &map);
+
+        let mut game = Game {
+            seed,
+            rng,
+            map,
+            camera,
+            entities,
+            player_key,
+            crosshair_key: None,
+            looking: false,
+            ui_data: UIData::new(),
+            message_log: MessageLog::new(),
+            high_scores: HighScores::default(),
+            morgue_dir: None,
+            themes: Themes::new(),
+            animations: Animations::new(),
+            killer: None,
+            echoes: vec![],
+            sounding: None,
+            turns_on_level: 0,
+            unrest: 0,
+            companion,
+            escaped: false,
+            recorded: false,
+            spawn_tables,
+        };
+        game.update_visibility(0);
+        game.refresh();
+        game
+    }
+
+    //A new run from another seed, keeping everything that isn't part of the run
+    pub fn new_run(&mut self, seed: u64) {
+        let viewport = self.camera.size;
+        let fresh = Game::start(seed, std::mem::take(&mut self.spawn_tables));
+        *self = Game {
+            high_scores: std::mem::take(&mut self.high_scores),
+            morgue_dir: self.morgue_dir.take(),
+            themes: std::mem::take(&mut self.themes),
+            animations: std::mem::take(&mut self.animations),
+            ..fresh
+        };
+        //The window didn't change size just because the run did
+        self.set_viewport(viewport);
+    }
+
+    pub fn player_pos(&self) -> Vector {
+        self.entities.get(self.player_key).unwrap().pos
+    }
+
+    pub fn is_dead(&self) -> bool {
+        self.entities[self.player_key].hp <= 0
+    }
+
+    pub fn has_escaped(&self) -> bool {
+        self.escaped
+    }
+
+    pub fn dog_status(&self) -> &'static str {
+        match &self.companion {
+            Some(companion) if companion.staying => "Your dog is staying put",
+            Some(_) => "Your dog is following you",
+            None => "You have no dog with you",
+        }
+    }
+
+    pub fn is_aiming(&self) -> bool {
+        self.crosshair_key.is_some() && !self.looking
+    }
+
+    //Looking spends no turn and changes nothing, so it is never recorded
+    pub fn start_looking(&mut self) {
+        if self.crosshair_key.is_none() {
+            let player_pos = self.player_pos();
+            self.crosshair_key = Some(
+                self.entities
+                    .insert_with_key(|k| Entity::new_crosshair(k, player_pos)),
+            );
+            self.looking = true;
+        }
+    }
+
+    pub fn stop_looking(&mut self) {
+        if self.looking {
+            self.remove_crosshair();
+            self.looking = false;
+        }
+    }
+
+    //Anywhere on the map, unlike aiming
+    pub fn move_cursor(&mut self, direction: Vector) {
+        if let Some(crosshair_key) = self.crosshair_key {
+            let future_pos = self.entities[crosshair_key].pos + direction;
+            if map::is_in_bounds(&self.map, future_pos) {
+                self.entities[crosshair_key].pos = future_pos;
+                self.camera.follow(future_pos, &self.map);
+            }
+        }
+    }
+
+    pub fn cursor_pos(&self) -> Option<Vector> {
+        self.crosshair_key
+            .map(|crosshair_key| self.entities[crosshair_key].pos)
+    }
+
+    //What the player knows about the cell under the cursor, one line each
+    pub fn describe_cursor(&self) -> Vec<String> {
+        let pos = match self.cursor_pos() {
+            Some(pos) => pos,
+            None => return vec![],
+        };
+        let tile = &self.map[pos.x as usize][pos.y as usize];
+        if !tile.explored {
+            return vec![String::from("You don't know what is there.")];
+        }
+
+        let mut lines = vec![];
+        if tile.visible {
+            lines.push(format!("{}: {}", tile.name, tile.description));
+            for entity in self.entities.values() {
+                if entity.pos != pos || !entity.is_in_fov || entity.name == "crosshair" {
+                    continue;
+                }
+                let mut line = format!("{}: {}", entity.name, entity.description);
+                if entity.hostile {
+                    line.push_str(&format!(" It looks {}.", entities::health(entity)));
+                }
+                lines.push(line);
+            }
+        } else {
+            lines.push(format!(
+                "{}: {} You remember it from earlier.",
+                tile.name, tile.description
+            ));
+        }
+        lines
+    }
+
+    //Resize the map window, e.g. when a terminal changes size
+    pub fn set_viewport(&mut self, size: Vector) {
+        self.camera.size = size;
+        let target = match self.crosshair_key {
+            Some(crosshair_key) => self.entities.get(crosshair_key).unwrap().pos,
+            None => self.player_pos(),
+        };
+        self.camera.follow(target, &self.map);
+    }
+
+    //Game logic for a single action, shared by every frontend and by replays
+    pub fn apply(&mut self, action: Action) {
+        let player_pos = self.player_pos();
+        self.animations.skip();
+
+        if self.is_dead() || self.escaped {
+            if let Action::Restart = action {
+                self.restart();
+            }
+        } else if self.is_choosing_perk() {
+            match action {
+                Action::Perk(perk) if perk.is_repeatable() || self.ui_data.ranks(perk) == 0 => {
+                    self.ui_data.perks.push(perk);
+                    self.ui_data.perk_choices -= 1;
+                    self.message_log.push(&format!("{}.", perk.title()));
+                }
+                _ => {}
+            }
+        } else if self.is_aiming() {
+            match action {
+                Action::Move(..) => {
+                    let crosshair_pos = self.entities.get(self.crosshair_key.unwrap()).unwrap().pos;
+                    let future_pos = crosshair_pos + action.direction();
+                    if map::is_in_bounds(&self.map, future_pos)
+                        && distance(player_pos, future_pos) <= self.throw_range()
+                    {
+                        let crosshair = self.entities.get_mut(self.crosshair_key.unwrap()).unwrap();
+                        crosshair.pos = future_pos;
+                        self.camera.follow(future_pos, &self.map);
+                    }
+                }
+                //It would only land back in their hands
+                Action::Fire if self.cursor_pos() == Some(player_pos) => {
+                    self.message_log
+                        .push("Aim somewhere other than your own feet.");
+                }
+                Action::Fire => {
+                    let target = self.remove_crosshair();
+                    self.throw_pebble(player_pos, target);
+                    self.end_turn(stealth::THROW_NOISE);
+                }
+                Action::Cancel => {
+                    self.remove_crosshair();
+                    self.message_log.push("You stopped throwing.");
+                }
+                _ => {}
+            }
+        } else {
+            match action {
+                Action::Move(..) => {
+                    let future_pos = player_pos + self.stumble(action.direction());
+                    let noise = match entities::blocking_at(&self.entities, future_pos) {
+                        //Trading places with the dog
+                        Some(key) if self.entities[key].ally => {
+                            self.entities[key].pos = player_pos;
+                            self.move_player(future_pos)
+                        }
+                        Some(key) => {
+                            self.hit(key, "You hit");
+                            stealth::FIGHT_NOISE
+                        }
+                        None if self.can_enter(future_pos) => self.move_player(future_pos),
+                        None if self
+                            .tile_at(future_pos)
+                            .is_some_and(|tile| tile.name == "lever") =>
+                        {
+                            self.pull_lever(future_pos);
+                            stealth::LEVER_NOISE
+                        }
+                        None => self.footstep_noise(),
+                    };
+                    //Climbing out ends the run, nothing gets another turn
+                    if self.escaped {
+                        return;
+                    }
+                    self.end_turn(noise);
+                    //Wading or climbing takes a second turn
+                    let tile =
+                        &self.map[self.player_pos().x as usize][self.player_pos().y as usize];
+                    if tile.slow && !self.is_dead() {
+                        self.end_turn(noise);
+                    }
+                }
+                Action::Wait => self.end_turn(0),
+                Action::Stay => match &mut self.companion {
+                    Some(companion) => {
+                        companion.toggle_stay();
+                        self.message_log.push(if companion.staying {
+                            "You tell your dog to stay."
+                        } else {
+                            "You call your dog to follow."
+                        });
+                    }
+                    None => self.message_log.push("You have no dog with you."),
+                },
+                Action::Search => {
+                    self.search();
+                    self.end_turn(0);
+                }
+                Action::Sneak => {
+                    self.ui_data.sneaking = !self.ui_data.sneaking;
+                    self.message_log.push(if self.ui_data.sneaking {
+                        "You crouch and move quietly."
+                    } else {
+                        "You stand up."
+                    });
+                    self.update_visibility(0);
+                }
+                Action::Throw => {
+                    if self.ui_data.pebbles == 0 {
+                        self.message_log.push("You have no pebbles to throw.");
+                    } else {
+                        self.crosshair_key = Some(
+                            self.entities
+                                .insert_with_key(|k| Entity::new_crosshair(k, player_pos)),
+                        );
+                        self.message_log.push("Where do you want to throw?");
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    pub fn cause_of_death(&self) -> String {
+        match self.killer {
+            Some(killer) => format!("a {}", killer),
+            None => String::from("the dark"),
+        }
+    }
+
+    //Only changes how the game looks, so it is never recorded
+    pub fn next_theme(&mut self) {
+        let name = self.themes.switch().name.clone();
+        self.message_log.push(&format!("Colours: {}.", name));
+    }
+
+    pub fn switch_animation_speed(&mut self) {
+        let speed = self.animations.switch_speed();
+        self.message_log
+            .push(&format!("Animations: {}.", speed.name()));
+    }
+
+    fn tile_at(&self, pos: Vector) -> Option<&map::Tile> {
+        if map::is_in_bounds(&self.map, pos) {
+            Some(&self.map[pos.x as usize][pos.y as usize])
+        } else {
+            None
+        }
+    }
+
+    //The player still knows the tile, and may still be looking at it
+    fn replace_tile(&mut self, pos: Vector, mut tile: Tile) {
+        let old = &self.map[pos.x as usize][pos.y as usize];
+        tile.explored = old.explored;
+        tile.visible = old.visible;
+        self.map[pos.x as usize][pos.y as usize] = tile;
+    }
+
+    //Whoever set it off, the trap shows from now on
+    fn spring_trap(&mut self, pos: Vector, trap: Trap, by_player: bool) {
+        self.replace_tile(pos, Tile::new_trap(trap));
+        match trap {
+            Trap::Pit if by_player => {
+                self.message_log.push("You fall into a hidden pit!");
+                self.hurt_player("pit");
+            }
+            Trap::Pit => self.message_log.push("The pebble drops into a hidden pit."),
+            Trap::Alarm => {
+                self.make_noise(pos, trap::ALARM_NOISE, "An alarm shrieks!");
+                if by_player {
+                    self.afflict_player(Status::Deafened, trap::DEAFEN_TURNS);
+                }
+            }
+            Trap::Rockfall => {
+                self.replace_tile(pos, Tile::new_rubble());
+                self.make_noise(
+                    pos,
+                    trap::ROCKFALL_NOISE,
+                    "Rocks crash down from the ceiling!",
+                );
+                if by_player {
+                    self.hurt_player("falling rock");
+                }
+            }
+        }
+    }
+
+    fn brush_fungus(&mut self) {
+        if self.rng.gen_range(0, 100) < status::SPORE_BLIND_PERC {
+            self.message_log
+                .push("Spores burst from the fungus into your eyes!");
+            self.afflict_player(Status::Blinded, status::SPORE_BLIND_TURNS);
+        }
+    }
+
+    fn hurt_player(&mut self, cause: &'static str) {
+        self.entities[self.player_key].hp -= 1;
+        self.killer = Some(cause);
+        self.afflict_player(Status::Stunned, trap::STUN_TURNS);
+    }
+
+    fn afflict_player(&mut self, status: Status, turns: u32) {
+        status::afflict(
+            &mut self.entities[self.player_key],
+            status,
+            turns,
+            &mut self.message_log,
+        );
+    }
+
+    //Feels the floor all around the player for traps
+    fn search(&mut self) {
+        let player_pos = self.player_pos();
+        let mut found = vec![];
+        for dx in -1..=1 {
+            for dy in -1..=1 {
+                let pos = player_pos + Vector::new(dx, dy);
+                if let Some(trap) = self.tile_at(pos).and_then(|tile| tile.hidden_trap()) {
+                    self.replace_tile(pos, Tile::new_trap(trap));
+                    found.push(trap);
+                }
+            }
+        }
+        if found.is_empty() {
+            self.message_log
+                .push("You search around, but find nothing.");
+        }
+        for trap in found {
+            self.message_log
+                .push(&format!("You find a hidden {}!", trap.name()));
+        }
+    }
+
+    //Plates only ever open their gates
+    fn press_plate(&mut self, pos: Vector) {
+        if let Some(wire) = self.map[pos.x as usize][pos.y as usize].wire {
+            self.message_log.push("The plate sinks with a click.");
+            self.move_gates(wire, true);
+        }
+    }
+
+    //Levers open closed gates and close open ones
+    fn pull_lever(&mut self, pos: Vector) {
+        if let Some(wire) = self.map[pos.x as usize][pos.y as usize].wire {
+            self.message_log.push("You pull the lever.");
+            let open = !map::gates_open(&self.map, wire);
+            self.move_gates(wire, open);
+        }
+    }
+
+    fn move_gates(&mut self, wire: u32, open: bool) {
+        let entities = &self.entities;
+        let moved = map::set_gates(&mut self.map, wire, open, |pos| {
+            entities::blocking_at(entities, pos).is_none()
+        });
+        if moved > 0 {
+            self.message_log.push(if open {
+                "You hear a gate grind open."
+            } else {
+                "You hear a gate slam shut."
+            });
+        }
+    }
+
+    fn can_enter(&self, pos: Vector) -> bool {
+        if map::is_walkable(&self.map, pos) {
+            return true;
+        }
+        if !map::is_in_bounds(&self.map, pos) {
+            return false;
+        }
+        match self.map[pos.x as usize][pos.y as usize].name.as_str() {
+            //Nothing below the bottom level but a fall to the death
+            "chasm" => self.ui_data.depth < map::DEEPEST,
+            "pool" => self.ui_data.ranks(Perk::Swimming) > 0,
+            _ => false,
+        }
+    }
+
+    //Returns the noise the step made
+    fn move_player(&mut self, pos: Vector) -> i32 {
+        self.entities[self.player_key].pos = pos;
+        self.camera.follow(pos, &self.map);
+        let tile = &self.map[pos.x as usize][pos.y as usize];
+        let noise = self.footstep_noise() + tile.noise;
+        if let Some(trap) = tile.hidden_trap() {
+            self.spring_trap(pos, trap, true);
+            return noise;
+        }
+        match tile.name.as_str() {
+            "stairs" => {
+                self.change_level(self.ui_data.depth + 1);
+                self.message_log
+                    .push("You climb down, deeper into the cave.");
+            }
+            "upstairs" => {
+                self.change_level(self.ui_data.depth - 1);
+                self.message_log
+                    .push("You climb up, back towards the surface.");
+            }
+            "exit" if self.ui_data.has_artifact => self.escape(),
+            "exit" => self
+                .message_log
+                .push("You won't leave without the artifact."),
+            "plate" => self.press_plate(pos),
+            "fungus" => self.brush_fungus(),
+            "chasm" => {
+                self.change_level(self.ui_data.depth + 1);
+                self.entities[self.player_key].hp -= FALL_DAMAGE;
+                self.killer = Some("fall");
+                self.message_log
+                    .push("You fall into the chasm and land hard on the level below.");
+            }
+            _ => {}
+        }
+        noise
+    }
+
+    //A fresh level above or below, the player keeps everything they carry
+    //Coming down they arrive at the way back up, going up at the stairs down
+    fn change_level(&mut self, depth: u32) {
+        let (map, upstairs, stairs) = generate_level(depth, &mut self.rng);
+        let arrival = if depth < self.ui_data.depth {
+            stairs
+        } else {
+            upstairs
+        };
+        let mut entities =
+            entities::generate(&self.spawn_tables, &map, arrival, depth, &mut self.rng);
+        if depth == map::DEEPEST && !self.ui_data.has_artifact {
+            entities.insert_with_key(|k| Entity::new_artifact(k, stairs));
+        }
+        let player_pos = self.player_pos();
+        self.player_key = entities.insert_with_key(|k| {
+            let mut player = self.entities[self.player_key];
+            player.key = k;
+            player.pos = arrival;
+            player
+        });
+        if let Some(mut companion) = self.companion.take() {
+            let dog = self.entities[companion.key];
+            if companion.comes_along(&self.entities, player_pos)
+                && companion.follow_player(dog, &mut entities, &map, arrival)
+            {
+                self.companion = Some(companion);
+            } else {
+                self.message_log.push("You leave your dog behind.");
+            }
+        }
+        self.camera.follow(arrival, &map);
+        self.map = map;
+        self.entities = entities;
+        self.ui_data.depth = depth;
+        self.echoes.clear();
+        self.turns_on_level = 0;
+        self.unrest = 0;
+        //Only new depths are worth anything
+        if depth > self.ui_data.deepest {
+            self.ui_data.deepest = depth;
+            self.gain_xp(progress::DESCEND_XP);
+        }
+    }
+
+    //The run ends like a death does, only recorded as a win
+    fn escape(&mut self) {
+        self.escaped = true;
+        self.recorded = true;
+        self.high_scores
+            .record(RunRecord::new(self.seed, &self.ui_data, true, "escaped"));
+        self.message_log
+            .push("You climb out into the daylight. You win!");
+        self.message_log
+            .push("R starts over, H shows high scores, Q quits.");
+        self.write_morgue();
+    }
+
+    //Removes the crosshair and returns where it was
+    fn remove_crosshair(&mut self) -> Vector {
+        let player_pos = self.player_pos();
+        let target = match self.crosshair_key.take() {
+            Some(crosshair_key) => self.entities.remove(crosshair_key).unwrap().pos,
+            None => player_pos,
+        };
+        self.camera.follow(player_pos, &self.map);
+        target
+    }
+
+    //The pebble flies until a wall or a creature stops it, then makes noise where it lands
+    fn throw_pebble(&mut self, from: Vector, to: Vector) {
+        let mut path = vec![];
+        let mut hit = None;
+        for point in get_line(from, to).into_iter().skip(1) {
+            //Pebbles fly over water and chasms, and through fungus
+            if !map::is_in_bounds(&self.map, point)
+                || self.map[point.x as usize][point.y as usize].blocks_projectiles
+            {
+                break;
+            }
+            path.push(point);
+            //Pebbles fly over the dog
+            hit = entities::blocking_at(&self.entities, point)
+                .filter(|key| !self.entities[*key].ally);
+            if hit.is_some() {
+                break;
+            }
+        }
+        let landing = path.last().copied().unwrap_or(from);
+        self.animations
+            .push(Effect::Projectile { path, glyph: 'o' });
+        if let Some(key) = hit {
+            self.hit(key, "Your pebble hits");
+        }
+
+        self.ui_data.pebbles -= 1;
+        self.ui_data.pebbles_thrown += 1;
+        let tile = &self.map[landing.x as usize][landing.y as usize];
+        if tile.name == "chasm" {
+            //Nothing to hear until it hits the bottom
+            self.echoes.push((landing, ECHO_DELAY));
+            self.message_log.push("The pebble drops into the chasm.");
+            return;
+        }
+        let hidden_trap = tile.hidden_trap();
+        if tile.name == "plate" {
+            self.press_plate(landing);
+        }
+        if let Some(trap) = hidden_trap {
+            self.spring_trap(landing, trap, false);
+            if trap == Trap::Pit {
+                return;
+            }
+        }
+        let tile = &self.map[landing.x as usize][landing.y as usize];
+        let message = if tile.swallows() {
+            "The pebble sinks with a splash."
+        } else {
+            let pebble = self
+                .entities
+                .insert_with_key(|k| Entity::new_thrown_pebble(k, landing));
+            if let Some(companion) = &mut self.companion {
+                companion.fetch(pebble);
+            }
+            "The pebble clatters in the dark."
+        };
+        let listeners = self.make_noise(landing, stealth::PEBBLE_NOISE, message);
+        if !self.has_status(Status::Deafened) {
+            self.sounding = Some((landing, listeners));
+        }
+    }
+
+    //Creatures around come to look, the player only notices if they can hear
+    //Returns the creatures that heard it
+    fn make_noise(&mut self, pos: Vector, loudness: i32, message: &str) -> Vec<DefaultKey> {
+        let listeners = ai::hear(&mut self.entities, pos, loudness);
+        self.unrest += loudness.max(0) as u32;
+        if self.has_status(Status::Deafened) {
+            return listeners;
+        }
+        self.message_log.push(message);
+        self.animations.push(Effect::Ripple {
+            center: pos,
+            radius: loudness,
+        });
+        listeners
+    }
+
+    //The dog's pebbles are picked up as soon as they're dropped, like any other
+    //A dog bitten to death is gone for the rest of the run
+    fn check_on_dog(&mut self) {
+        let dog = match &self.companion {
+            Some(companion) => self.entities[companion.key],
+            None => return,
+        };
+        if dog.hp > 0 {
+            return;
+        }
+        self.entities.remove(dog.key);
+        self.companion = None;
+        self.message_log.push(if dog.is_in_fov {
+            "Your dog falls still."
+        } else {
+            "You hear your dog yelp, then nothing."
+        });
+    }
+
+    fn companion_turn(&mut self) {
+        let companion = match &mut self.companion {
+            Some(companion) => companion,
+            None => return,
+        };
+        let barked = companion.take_turn(
+            &mut self.entities,
+            &self.map,
+            self.player_key,
+            &mut self.message_log,
+        );
+        let dog_pos = self.entities[companion.key].pos;
+        let player_pos = self.player_pos();
+        entities::pickup(
+            &mut self.entities,
+            player_pos,
+            &mut self.ui_data,
+            &mut self.message_log,
+        );
+        if barked {
+            self.make_noise(
+                dog_pos,
+                companion::BARK_NOISE,
+                "Your dog barks at something in the dark.",
+            );
+        }
+    }
+
+    //Lingering and noise draw new creatures onto the level, out of the player's sight
+    fn stir(&mut self, noise: i32) {
+        self.turns_on_level += 1;
+        self.unrest += 1 + self.turns_on_level / LINGER_TURNS + noise.max(0) as u32;
+        if self.unrest < UNREST_TO_SPAWN {
+            return;
+        }
+        self.unrest = 0;
+        let player_pos = self.player_pos();
+        let spawned = entities::spawn_wanderer(
+            &self.spawn_tables,
+            &mut self.entities,
+            &self.map,
+            player_pos,
+            self.ui_data.depth,
+            &mut self.rng,
+        );
+        if spawned.is_some() && !self.has_status(Status::Deafened) {
+            self.message_log
+                .push("You hear something stirring in the dark.");
+        }
+    }
+
+    fn count_down_echoes(&mut self) {
+        let mut heard = vec![];
+        self.echoes.retain_mut(|(pos, turns)| {
+            *turns -= 1;
+            if *turns == 0 {
+                heard.push(*pos);
+            }
+            *turns > 0
+        });
+        for pos in heard {
+            self.make_noise(
+                pos,
+                stealth::PEBBLE_NOISE,
+                "A faint clatter echoes up from the chasm.",
+            );
+        }
+    }
+
+    fn hit(&mut self, key: DefaultKey, verb: &str) {
+        let creature = self.entities.get_mut(key).unwrap();
+        creature.hp -= 1;
+        let name = creature.name;
+        self.animations.push(Effect::Flash {
+            pos: creature.pos,
+            glyph: creature.glyph,
+        });
+        self.message_log.push(&format!("{} the {}.", verb, name));
+        if creature.hp <= 0 {
+            self.entities.remove(key);
+            self.ui_data.kills += 1;
+            self.gain_xp(progress::KILL_XP);
+            self.message_log.push(&format!("The {} dies.", name));
+        }
+    }
+
+    pub fn throw_range(&self) -> i32 {
+        THROW_RANGE + self.ui_data.ranks(Perk::ThrowRange) as i32 * progress::THROW_BONUS
+    }
+
+    fn footstep_noise(&self) -> i32 {
+        let noise = if self.ui_data.sneaking {
+            stealth::SNEAK_NOISE
+        } else {
+            stealth::MOVE_NOISE
+        };
+        let quiet = self.ui_data.ranks(Perk::QuietSteps) as i32 * progress::QUIET_BONUS;
+        (noise - quiet).max(0)
+    }
+
+    pub fn is_choosing_perk(&self) -> bool {
+        self.ui_data.perk_choices > 0
+    }
+
+    //Every level-up raises max hp and earns a perk to pick
+    fn gain_xp(&mut self, xp: u32) {
+        self.ui_data.xp += xp;
+        while self.ui_data.xp >= progress::xp_to_next(self.ui_data.level) {
+            self.ui_data.xp -= progress::xp_to_next(self.ui_data.level);
+            self.ui_data.level += 1;
+            self.ui_data.perk_choices += 1;
+            let player = &mut self.entities[self.player_key];
+            player.max_hp += progress::HP_PER_LEVEL;
+            player.hp += progress::HP_PER_LEVEL;
+            self.message_log.push(&format!(
+                "You reach level {}! Pick a perk.",
+                self.ui_data.level
+            ));
+        }
+    }
+
+    pub fn has_status(&self, status: Status) -> bool {
+        self.entities[self.player_key].statuses.has(status)
+    }
+
+    pub fn sight_radius(&self) -> i32 {
+        if self.has_status(Status::Blinded) {
+            0
+        } else {
+            let bonus = self.ui_data.ranks(Perk::WideLight) * progress::LIGHT_BONUS;
+            torch::sight_radius(self.ui_data.fuel, bonus)
+        }
+    }
+
+    //A stunned player goes any way but the one they chose
+    fn stumble(&mut self, direction: Vector) -> Vector {
+        if !self.has_status(Status::Stunned) {
+            return direction;
+        }
+        let (dx, dy) = ai::DIRECTIONS[self.rng.gen_range(0, ai::DIRECTIONS.len())];
+        self.message_log.push("You stumble.");
+        Vector::new(dx, dy)
+    }
+
+    //Poison hurts first, then every status loses a turn
+    fn tick_statuses(&mut self) {
+        let player = &mut self.entities[self.player_key];
+        if player.statuses.has(Status::Poisoned) {
+            let was_alive = player.hp > 0;
+            player.hp -= 1;
+            //Only the dose that actually kills gets the blame
+            if was_alive && player.hp <= 0 {
+                self.killer = Some("dose of poison");
+            }
+            self.message_log.push("The poison burns in your veins.");
+        }
+        status::tick(&mut self.entities[self.player_key], &mut self.message_log);
+    }
+
+    fn burn_torch(&mut self) {
+        if self.ui_data.fuel == 0 {
+            return;
+        }
+        self.ui_data.fuel -= 1;
+        if self.ui_data.fuel == torch::LOW_FUEL {
+            self.message_log.push("Your torch flickers.");
+        } else if self.ui_data.fuel == 0 {
+            self.message_log.push("Your torch goes out.");
+        }
+    }
+
+    fn update_visibility(&mut self, noise: i32) {
+        let player_pos = self.player_pos();
+        let light = self.map[player_pos.x as usize][player_pos.y as usize].light;
+        self.ui_data.visibility = stealth::visibility(light, noise, self.ui_data.sneaking);
+    }
+
+    //Everything that happens once the player has spent a turn
+    //Everything but the player acts, stopping as soon as the player dies
+    fn world_turn(&mut self, noise: i32) {
+        //A hasted player gets every other turn for free
+        if !self.has_status(Status::Hasted) || self.ui_data.turn % 2 == 0 {
+            let attacker = ai::take_turns(
+                &mut self.entities,
+                &self.map,
+                self.player_key,
+                self.ui_data.visibility,
+                &mut self.rng,
+                &mut self.message_log,
+            );
+            self.killer = attacker.or(self.killer);
+        }
+        if self.is_dead() {
+            return;
+        }
+        self.check_on_dog();
+        self.companion_turn();
+        self.tick_statuses();
+        if self.is_dead() {
+            return;
+        }
+        self.count_down_echoes();
+        self.stir(noise);
+    }
+
+    fn end_turn(&mut self, noise: i32) {
+        let player_pos = self.player_pos();
+
+        let picked_up = entities::pickup(
+            &mut self.entities,
+            player_pos,
+            &mut self.ui_data,
+            &mut self.message_log,
+        );
+        self.gain_xp(picked_up * progress::PICKUP_XP);
+        let noise = if picked_up > 0 {
+            noise + stealth::PICKUP_NOISE
+        } else {
+            noise
+        };
+        self.update_visibility(noise);
+
+        let hp = self.entities[self.player_key].hp;
+        self.world_turn(noise);
+        let player = self.entities[self.player_key];
+        if player.hp < hp {
+            self.animations.push(Effect::Flash {
+                pos: player.pos,
+                glyph: player.glyph,
+            });
+            if player.hp == 1 {
+                self.afflict_player(Status::Hasted, status::FRIGHT_HASTE_TURNS);
+            }
+        }
+        self.burn_torch();
+        self.ui_data.turn += 1;
+        self.refresh();
+    }
+
+    fn refresh(&mut self) {
+        let player_pos = self.player_pos();
+        let radius = self.sight_radius();
+        entities::compute_fov(&mut self.entities, &self.map, player_pos, radius);
+        let mut explored = map::compute_fov(&mut self.map, player_pos, radius);
+        //The clatter of a landed pebble sketches the cave around it, and whoever turned to listen
+        if let Some((center, listeners)) = self.sounding.take() {
+            explored += map::reveal(&mut self.map, center, SOUNDING_RADIUS);
+            for key in listeners {
+                if let Some(creature) = self.entities.get_mut(key) {
+                    creature.is_in_fov = true;
+                }
+            }
+        }
+        self.gain_xp(explored * progress::EXPLORE_XP);
+
+        if self.is_dead() && !self.recorded {
+            self.recorded = true;
+            let cause = self.cause_of_death();
+            self.high_scores
+                .record(RunRecord::new(self.seed, &self.ui_data, false, &cause));
+            self.message_log
+                .push("You died. R restarts, H shows high scores, Q quits.");
+            self.write_morgue();
+        }
+    }
+
+    fn write_morgue(&mut self) {
+        if let Some(dir) = &self.morgue_dir {
+            match morgue::save(dir, self) {
+                Ok(path) => self
+                    .message_log
+                    .push(&format!("Morgue file written to {}.", path.display())),
+                Err(_) => self.message_log.push("Could not write the morgue file."),
+            }
+        }
+    }
+
+    //The next seed comes from this run's rng, so restarts inside a replay stay deterministic
+    //and every run is recorded with the seed that rebuilds it
+    fn restart(&mut self) {
+        let seed = self.rng.gen();
+        self.new_run(seed);
+    }
+
+    pub fn draw(&self, renderer: &mut dyn Renderer, screen: Vector) {
+        let theme = self.themes.current();
+        map::draw_map(renderer, &self.map, &self.camera, theme);
+        entities::draw_entities(renderer, &self.entities, &self.camera, theme);
+        animation::draw_animations(renderer, &self.animations, &self.map, &self.camera, theme);
+        ui::draw_ui(
+            renderer,
+            &self.ui_data,
+            &self.entities[self.player_key].statuses,
+            &self.message_log,
+            self.camera.size,
+            screen,
+            theme,
+        );
+    }
+}
+
+//A cave for the depth, with the way up where the player starts and where the stairs down are
+//On the bottom level the artifact takes the place of the stairs
+fn generate_level(depth: u32, rng: &mut StdRng) -> (Map, Vector, Vector) {
+    let (mut map, spawn) = map::generate(rng);
+    let stairs = map::connect(&mut map, spawn, depth).unwrap_or(spawn);
+    (map, spawn, stairs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stairs_are_reachable_from_the_start() {
+        for seed in 0..12 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            for depth in 1..=map::DEEPEST {
+                let (map, spawn, stairs) = generate_level(depth, &mut rng);
+                assert_ne!(spawn, stairs, "seed {} depth {}", seed, depth);
+                let steps = map::steps_from(&map, spawn);
+                assert!(
+                    steps[stairs.x as usize][stairs.y as usize].is_some(),
+                    "seed {} depth {}",
+                    seed,
+                    depth
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn poison_is_only_blamed_for_the_last_hit_point() {
+        let mut game = Game::new(1);
+        let player_key = game.player_key;
+        game.afflict_player(Status::Poisoned, 3);
+        game.killer = Some("crawler");
+
+        game.entities[player_key].hp = 0;
+        game.tick_statuses();
+        assert_eq!(game.cause_of_death(), "a crawler");
+
+        game.entities[player_key].hp = 1;
+        game.tick_statuses();
+        assert_eq!(game.cause_of_death(), "a dose of poison");
+    }
+
+    #[test]
+    fn restarting_keeps_the_viewport() {
+        let mut game = Game::new(1);
+        let viewport = Vector::new(100, 30);
+        game.set_viewport(viewport);
+        let player_key = game.player_key;
+        game.entities[player_key].hp = 0;
+        game.apply(Action::Restart);
+        assert!(!game.is_dead());
+        assert_eq!(game.camera.size, viewport);
+    }
+
+    #[test]
+    fn a_dog_bitten_to_death_is_gone() {
+        let mut game = Game::new(1);
+        let dog = game.companion.as_ref().unwrap().key;
+        game.entities[dog].hp = 0;
+        game.apply(Action::Wait);
+        assert!(game.companion.is_none());
+        assert!(!game.entities.contains_key(dog));
+        assert_eq!(game.dog_status(), "You have no dog with you");
+    }
+
+    #[test]
+    fn torch_flickers_then_goes_out() {
+        let mut game = Game::new(1);
+        game.ui_data.fuel = torch::LOW_FUEL + 1;
+        game.burn_torch();
+        assert_eq!(game.message_log.log.last().unwrap(), "Your torch flickers.");
+        game.ui_data.fuel = 2;
+        for _ in 0..3 {
+            game.burn_torch();
+        }
+        assert_eq!(game.ui_data.fuel, 0);
+        let out = game
+            .message_log
+            .log
+            .iter()
+            .filter(|message| *message == "Your torch goes out.")
+            .count();
+        assert_eq!(out, 1);
+        assert_eq!(game.sight_radius(), 1);
+    }
+
+    #[test]
+    fn escaping_ends_the_run_once() {
+        let mut game = Game::new(1);
+        game.ui_data.has_artifact = true;
+        let exit = game.player_pos();
+        let (dx, dy) = [(1, 0), (-1, 0), (0, 1), (0, -1)]
+            .iter()
+            .copied()
+            .find(|(dx, dy)| {
+                game.tile_at(exit + Vector::new(*dx, *dy))
+                    .is_some_and(|tile| tile.name == "floor" && tile.trap.is_none())
+            })
+            .unwrap();
+        game.apply(Action::Move(dx, dy));
+        let turn = game.ui_data.turn;
+        game.apply(Action::Move(-dx, -dy));
+        assert!(game.has_escaped());
+        assert_eq!(game.ui_data.turn, turn);
+        game.apply(Action::Wait);
+        assert_eq!(game.high_scores.runs.len(), 1);
+        assert!(game.high_scores.runs[0].won);
+    }
+}
@@ -0,0 +1,23 @@
+pub mod ai;
+pub mod animation;
+pub mod camera;
+pub mod companion;
+pub mod data;
+pub mod entities;
+pub mod game;
+pub mod map;
+pub mod morgue;
+pub mod options;
+pub mod progress;
+pub mod render;
+pub mod replay;
+pub mod scene;
+pub mod score;
+pub mod spawn;
+pub mod status;
+pub mod stealth;
+pub mod theme;
+pub mod torch;
+pub mod trap;
+pub mod ui;
+pub mod vault;
@@ -1,213 +1,210 @@
-mod entities;
-mod map;
-mod ui;
-
-use crate::entities::Entity;
-use crate::map::{Map, Palette};
-use crate::ui::{MessageLog, UIData};
 use quicksilver::{
-    geom::{Rectangle, Vector},
-    graphics::{Color, Font, FontStyle, Image},
+    combinators::Either,
+    graphics::{Font, Image},
     input::{ButtonState::*, Key},
     lifecycle::{run, Asset, Settings, State, Window},
-    Future, Result,
+    load_file, Future, Result,
 };
-use slotmap::{DefaultKey, SlotMap};
-use std::collections::HashMap;
-
-enum GameState {
-    Moving,
-    Throwing,
-    Dead,
-}
-
-struct Game {
-    tileset: Asset<HashMap<char, Image>>,
-    map: Map,
-    entities: SlotMap<DefaultKey, Entity>,
-    player_key: DefaultKey,
-    crosshair_key: Option<DefaultKey>,
-    ui_data: UIData,
-    message_log: MessageLog,
-    state: GameState,
+use rl_2019::game::Game;
+use rl_2019::map;
+use rl_2019::options::Options;
+use rl_2019::render::{QuicksilverRenderer, SheetMapping, Tileset, SCREEN_SIZE};
+use rl_2019::replay::Session;
+use rl_2019::scene::{self, Command, SceneStack};
+use rl_2019::score::HighScores;
+use rl_2019::theme::Themes;
+use std::sync::OnceLock;
+
+//Parsed before quicksilver moves the working directory into static/
+static OPTIONS: OnceLock<Options> = OnceLock::new();
+
+struct App {
+    tileset: Asset<Tileset>,
+    game: Game,
+    session: Session,
+    scenes: SceneStack,
 }
 
-impl State for Game {
+impl State for App {
     //Load assets and initialize
     fn new() -> Result<Self> {
         let square_font = "square.ttf";
-
-        let (map, player_spawn) = map::generate();
-        let mut entities = entities::generate(&map);
-
-        let player_key = entities.insert_with_key(|k| Entity::new_player(k, player_spawn));
-
-        let tileset = Asset::new(Font::load(square_font).and_then(move |text| {
-            let tiles = text
-                .render(map::GLYPHS, &FontStyle::new(map::TILE_SIZE.y, Color::WHITE))
-                .expect("Could not render the font tileset.");
-
-            let mut tileset = HashMap::new();
-            for (index, glyph) in map::GLYPHS.chars().enumerate() {
-                let pos = (index as i32 * map::TILE_SIZE.x as i32, 0);
-                let tile = tiles.subimage(Rectangle::new(pos, map::TILE_SIZE));
-                tileset.insert(glyph, tile);
-            }
-
-            Ok(tileset)
-        }));
-
-        let ui_text = Asset::new(Font::load(square_font).and_then(move |text| {
-            let mut texts = HashMap::new();
-            let style = FontStyle::new(12.0, Palette::WHITE);
-            let log_style = FontStyle::new(9.0, Palette::WHITE);
-            texts.insert("pebbles", text.render("Pebbles: ", &style)?);
-            texts.insert("message_log", text.render("Messages:", &style)?);
-            texts.insert("dark", text.render("It's dark around you...", &log_style)?);
-            texts.insert("pickup", text.render("You pickup a pebble", &log_style)?);
-            texts.insert(
-                "throw_mode_enter",
-                text.render("Where do you want to throw?", &log_style)?,
-            );
-            texts.insert(
-                "throw_mode_exit",
-                text.render("You stopped throwing.", &log_style)?,
-            );
-            texts.insert(
-                "died",
-                text.render("You died. Press R to restart or Q to quit.", &log_style)?,
-            );
-            Ok(texts)
-        }));
+        let options = OPTIONS
+            .get()
+            .expect("Options are parsed before the game starts.");
+
+        let session = Session::start(options).expect("Could not load the replay.");
+        let mut game = Game::new(session.seed);
+        game.themes = Themes::load(&options.themes);
+        game.animations.speed = options.animations;
+        let scenes = if session.playback.is_some() {
+            game.message_log.push("Replaying a recorded run.");
+            SceneStack::playing()
+        } else {
+            game.high_scores = HighScores::load();
+            game.morgue_dir = Some(options.morgue.clone());
+            SceneStack::title()
+        };
+
+        let font = Font::load(square_font);
+        let tileset = Asset::new(match &options.tileset {
+            Some(mapping) => Either::A(
+                load_file(mapping.clone())
+                    .and_then(|bytes| Ok(SheetMapping::parse(&String::from_utf8_lossy(&bytes))?))
+                    .and_then(|mapping| {
+                        Image::load(mapping.image.clone())
+                            .join(font)
+                            .and_then(|(sheet, font)| Tileset::from_sheet(font, mapping, sheet))
+                    }),
+            ),
+            None => Either::B(font.and_then(Tileset::from_font)),
+        });
 
         Ok(Self {
-            map,
-            entities,
             tileset,
-            player_key,
-            crosshair_key: None,
-            ui_data: UIData::new(ui_text),
-            message_log: MessageLog::new(),
-            state: GameState::Moving,
+            game,
+            session,
+            scenes,
         })
     }
 
     //Process keyboard, mouse, update game state
     fn update(&mut self, window: &mut Window) -> Result<()> {
-        let player_pos = self.entities.get(self.player_key).unwrap().pos;
-        
-        match &mut self.state {
-            GameState::Moving => {
-                let mut direction = Vector::ZERO;
-                if window.keyboard()[Key::Right] == Pressed {
-                    direction = Vector::new(1, 0);
-                }
-                if window.keyboard()[Key::Left] == Pressed {
-                    direction = Vector::new(-1, 0);
-                }
-                if window.keyboard()[Key::Up] == Pressed {
-                    direction = Vector::new(0, -1);
-                }
-                if window.keyboard()[Key::Down] == Pressed {
-                    direction = Vector::new(0, 1);
+        let keys = pressed_keys(window);
+        match &mut self.session.playback {
+            Some(playback) => {
+                let keyboard = window.keyboard();
+                if keyboard[Key::Space] == Pressed {
+                    playback.paused = !playback.paused;
                 }
-                if window.keyboard()[Key::T] == Pressed {
-                    self.crosshair_key = Some(
-                        self.entities
-                            .insert_with_key(|k| Entity::new_crosshair(k, player_pos)),
-                    );
-                    self.message_log.push("throw_mode_enter");
-                    self.state = GameState::Throwing;
+                if keyboard[Key::Equals] == Pressed || keyboard[Key::Add] == Pressed {
+                    playback.faster();
                 }
-
-                let future_pos =
-                    Vector::new(player_pos.x + direction.x, player_pos.y + direction.y);
-                if !self.map[future_pos.x as usize][future_pos.y as usize].blocks
-                    && map::is_in_bounds(future_pos)
-                {
-                    self.entities.get_mut(self.player_key).unwrap().pos = future_pos
-                }
-            }
-            GameState::Throwing => {
-                let crosshair_pos = self.entities.get(self.crosshair_key.unwrap()).unwrap().pos;
-                let mut crosshair_direction = Vector::ZERO;
-
-                if window.keyboard()[Key::Escape] == Pressed {
-                    self.message_log.push("throw_mode_exit");
-                    self.state = GameState::Moving;
+                if keyboard[Key::Minus] == Pressed || keyboard[Key::Subtract] == Pressed {
+                    playback.slower();
                 }
-                if window.keyboard()[Key::Right] == Pressed {
-                    crosshair_direction = Vector::new(1, 0);
-                }
-                if window.keyboard()[Key::Left] == Pressed {
-                    crosshair_direction = Vector::new(-1, 0);
+                let action = if playback.paused && keyboard[Key::Period] == Pressed {
+                    playback.step()
+                } else {
+                    playback.tick()
+                };
+                if keyboard[Key::Q] == Pressed {
+                    window.close();
                 }
-                if window.keyboard()[Key::Up] == Pressed {
-                    crosshair_direction = Vector::new(0, -1);
+                if playback.just_finished() {
+                    self.game.message_log.push("The replay is over.");
                 }
-                if window.keyboard()[Key::Down] == Pressed {
-                    crosshair_direction = Vector::new(0, 1);
+                for key in keys {
+                    self.scenes.handle_global(key, &mut self.game);
                 }
-
-                let future_pos = Vector::new(
-                    crosshair_pos.x + crosshair_direction.x,
-                    crosshair_pos.y + crosshair_direction.y,
-                );
-                if map::is_in_bounds(future_pos) {
-                    let crosshair = self.entities.get_mut(self.crosshair_key.unwrap()).unwrap();
-                    crosshair.pos = future_pos;
+                if let Some(action) = action {
+                    self.game.apply(action);
                 }
             }
-            GameState::Dead => {
-                if window.keyboard()[Key::Q] == Pressed {
-                    window.close();
-                }
-                if window.keyboard()[Key::R] == Pressed {
-                    let (map, _player_spawn) = map::generate();
-                    self.map = map;
-                    self.ui_data.pebbles = 0;
+            None => {
+                for key in keys {
+                    match self.scenes.handle(key, &mut self.game) {
+                        Some(Command::Act(action)) => {
+                            self.session.commit(action);
+                            self.game.apply(action);
+                        }
+                        Some(Command::NewGame(seed)) => {
+                            self.session.record_run(seed);
+                            self.game.new_run(seed);
+                        }
+                        Some(Command::Quit) => window.close(),
+                        None => {}
+                    }
+                    self.scenes.update(&self.game);
                 }
             }
         }
 
-        entities::pickup(
-            &mut self.entities,
-            player_pos,
-            &mut self.ui_data,
-            &mut self.message_log,
-        );
-        entities::compute_fov(&mut self.entities, player_pos);
-        map::compute_fov(&mut self.map, player_pos);
+        self.scenes.update(&self.game);
+        self.game.animations.tick();
         Ok(())
     }
 
     //Draw stuff
     fn draw(&mut self, window: &mut Window) -> Result<()> {
-        window.clear(Palette::DARK_BLUE)?;
-
-        let tileset = &mut self.tileset;
-
-        let map = &self.map;
-        let entities = &self.entities;
-        let ui_data = &mut self.ui_data;
-        let message_log = &mut self.message_log;
+        window.clear(self.game.themes.current().background)?;
 
-        tileset.execute(|tileset| {
-            map::draw_map(window, map, tileset);
-            entities::draw_entities(window, entities, tileset);
-            ui::draw_ui(window, ui_data, message_log, tileset);
+        let (game, scenes) = (&self.game, &self.scenes);
+        self.tileset.execute(|tileset| {
+            let mut renderer = QuicksilverRenderer::new(window, tileset);
+            scenes.draw(game, &mut renderer, SCREEN_SIZE);
             Ok(())
         })?;
         Ok(())
     }
 }
 
+//Keys the scenes understand, letters are passed on lower case
+const KEYS: [(Key, scene::Key); 8] = [
+    (Key::Up, scene::Key::Up),
+    (Key::Down, scene::Key::Down),
+    (Key::Left, scene::Key::Left),
+    (Key::Right, scene::Key::Right),
+    (Key::Return, scene::Key::Enter),
+    (Key::Escape, scene::Key::Escape),
+    (Key::Period, scene::Key::Char('.')),
+    (Key::Slash, scene::Key::Char('?')),
+];
+
+const LETTERS: [Key; 26] = [
+    Key::A,
+    Key::B,
+    Key::C,
+    Key::D,
+    Key::E,
+    Key::F,
+    Key::G,
+    Key::H,
+    Key::I,
+    Key::J,
+    Key::K,
+    Key::L,
+    Key::M,
+    Key::N,
+    Key::O,
+    Key::P,
+    Key::Q,
+    Key::R,
+    Key::S,
+    Key::T,
+    Key::U,
+    Key::V,
+    Key::W,
+    Key::X,
+    Key::Y,
+    Key::Z,
+];
+
+fn pressed_keys(window: &Window) -> Vec<scene::Key> {
+    let keyboard = window.keyboard();
+    let mut keys: Vec<scene::Key> = KEYS
+        .iter()
+        .filter(|(key, _)| keyboard[*key] == Pressed)
+        .map(|(_, key)| *key)
+        .collect();
+    keys.extend(
+        ('a'..='z')
+            .zip(LETTERS.iter())
+            .filter(|(_, key)| keyboard[**key] == Pressed)
+            .map(|(letter, _)| scene::Key::Char(letter)),
+    );
+    keys
+}
+
 fn main() {
     std::env::set_var("WINIT_HIDPI_FACTOR", "1.0");
+    if OPTIONS.set(Options::from_args()).is_err() {
+        unreachable!("Options are only parsed once.");
+    }
     let settings = Settings {
         scale: quicksilver::graphics::ImageScaleStrategy::Blur,
         ..Default::default()
     };
 
-    run::<Game>("7DRL 2019", map::WINDOW_SIZE, settings);
+    run::<App>("7DRL 2019", map::WINDOW_SIZE, settings);
 }
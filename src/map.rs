@@ -1,11 +1,13 @@
 extern crate rand;
 
-use quicksilver::{
-    geom::{Rectangle, Vector},
-    graphics::{Background::Blended, Background::Col, Color, Image},
-    lifecycle::Window,
-};
-use std::collections::HashMap;
+use crate::camera::Camera;
+use crate::render::Renderer;
+use crate::theme::Theme;
+use crate::trap::{Trap, TRAPS};
+use crate::vault::{self, Vault};
+use quicksilver::{geom::Vector, graphics::Color};
+use rand::rngs::StdRng;
+use std::collections::VecDeque;
 
 pub type Map = Vec<Vec<Tile>>;
 
@@ -114,34 +116,396 @@ impl Palette {
 #[derive(Clone, Debug, PartialEq)]
 pub struct Tile {
     pub name: String,
+    //Shown by the look mode
+    pub description: &'static str,
     pub glyph: char,
     pub color: Color,
-    pub is_in_fov: bool,
+    //Seen at some point, and seen right now
+    pub explored: bool,
+    pub visible: bool,
     pub color_in_fov: Color,
-    pub blocks: bool,
+    pub blocks_movement: bool,
+    pub blocks_sight: bool,
+    //Stops thrown pebbles
+    pub blocks_projectiles: bool,
+    pub light: i32,
+    //Crossing it takes an extra turn
+    pub slow: bool,
+    //Added to the noise of stepping onto it
+    pub noise: i32,
+    //Gates open and close with the levers and plates on the same wire
+    pub wire: Option<u32>,
+    //Hidden traps look like floor until they're found
+    pub trap: Option<Trap>,
 }
 
 impl Tile {
     fn new_wall() -> Tile {
         Tile {
             name: String::from("wall"),
+            description: "Rough cave rock.",
             glyph: '#',
             color: Palette::DARK_BLUE,
-            is_in_fov: false,
+            explored: false,
+            visible: false,
             color_in_fov: Palette::WHITE,
-            blocks: true,
+            blocks_movement: true,
+            blocks_sight: true,
+            blocks_projectiles: true,
+            light: 0,
+            slow: false,
+            noise: 0,
+            wire: None,
+            trap: None,
         }
     }
     fn new_floor() -> Tile {
         Tile {
             name: String::from("floor"),
+            description: "Bare cave floor.",
             glyph: ' ',
             color: Palette::DARK_BLUE,
-            is_in_fov: false,
+            explored: false,
+            visible: false,
             color_in_fov: Palette::WHITE,
-            blocks: false,
+            blocks_movement: false,
+            blocks_sight: false,
+            blocks_projectiles: false,
+            light: 0,
+            slow: false,
+            noise: 0,
+            wire: None,
+            trap: None,
+        }
+    }
+    fn new_stairs() -> Tile {
+        Tile {
+            name: String::from("stairs"),
+            description: "A narrow passage leading further down.",
+            glyph: '>',
+            color: Palette::DARK_BLUE,
+            explored: false,
+            visible: false,
+            color_in_fov: Palette::YELLOW,
+            blocks_movement: false,
+            blocks_sight: false,
+            blocks_projectiles: false,
+            light: 0,
+            slow: false,
+            noise: 0,
+            wire: None,
+            trap: None,
+        }
+    }
+    fn new_upstairs() -> Tile {
+        Tile {
+            name: String::from("upstairs"),
+            description: "A narrow passage leading back up.",
+            glyph: '<',
+            ..Tile::new_stairs()
+        }
+    }
+    //The way up on the first level
+    fn new_exit() -> Tile {
+        Tile {
+            name: String::from("exit"),
+            description: "Daylight, far above. The way out of the cave.",
+            glyph: '<',
+            ..Tile::new_stairs()
+        }
+    }
+    //Glows faintly, so it shows even outside the field of view
+    fn new_moss() -> Tile {
+        Tile {
+            name: String::from("moss"),
+            description: "Glowing moss, it lights up everything around it.",
+            glyph: ':',
+            color: Palette::DARK_GREEN,
+            explored: false,
+            visible: false,
+            color_in_fov: Palette::LIGHT_GREEN,
+            blocks_movement: false,
+            blocks_sight: false,
+            blocks_projectiles: false,
+            light: 0,
+            slow: false,
+            noise: 0,
+            wire: None,
+            trap: None,
+        }
+    }
+    fn new_shallows() -> Tile {
+        Tile {
+            name: String::from("shallows"),
+            description: "Knee-deep water, slow and loud to wade through.",
+            glyph: '~',
+            color: Palette::DARK_BLUE,
+            explored: false,
+            visible: false,
+            color_in_fov: Palette::LIGHT_BLUE,
+            blocks_movement: false,
+            blocks_sight: false,
+            blocks_projectiles: false,
+            light: 0,
+            slow: true,
+            noise: 2,
+            wire: None,
+            trap: None,
+        }
+    }
+    //Only swimmers get across, pebbles thrown in sink like in the shallows
+    fn new_pool() -> Tile {
+        Tile {
+            name: String::from("pool"),
+            description: "Deep, still water. Only a good swimmer gets across.",
+            glyph: '=',
+            color: Palette::DARK_BLUE,
+            explored: false,
+            visible: false,
+            color_in_fov: Palette::INDIGO,
+            blocks_movement: false,
+            blocks_sight: false,
+            blocks_projectiles: false,
+            light: 0,
+            slow: true,
+            noise: 2,
+            wire: None,
+            trap: None,
+        }
+    }
+    //Whatever goes in ends up on the next level down
+    fn new_chasm() -> Tile {
+        Tile {
+            name: String::from("chasm"),
+            description: "A crack in the floor, you can't see the bottom.",
+            glyph: ';',
+            color: Palette::DARK_BLUE,
+            explored: false,
+            visible: false,
+            color_in_fov: Palette::PURPLE,
+            blocks_movement: false,
+            blocks_sight: false,
+            blocks_projectiles: false,
+            light: 0,
+            slow: false,
+            noise: 0,
+            wire: None,
+            trap: None,
+        }
+    }
+    pub fn new_rubble() -> Tile {
+        Tile {
+            name: String::from("rubble"),
+            description: "Loose rocks, slow and noisy to climb over.",
+            glyph: '%',
+            color: Palette::DARK_BLUE,
+            explored: false,
+            visible: false,
+            color_in_fov: Palette::BROWN,
+            blocks_movement: false,
+            blocks_sight: false,
+            blocks_projectiles: false,
+            light: 0,
+            slow: true,
+            noise: 2,
+            wire: None,
+            trap: None,
+        }
+    }
+
+    //A pillar you can see past, but not walk or throw through
+    fn new_stalagmite() -> Tile {
+        Tile {
+            name: String::from("stalagmite"),
+            description: "A rock pillar growing from the floor, thin enough to see around.",
+            glyph: 'I',
+            color: Palette::DARK_BLUE,
+            explored: false,
+            visible: false,
+            color_in_fov: Palette::LIGHT_GRAY,
+            blocks_movement: true,
+            blocks_sight: false,
+            blocks_projectiles: true,
+            light: 0,
+            slow: false,
+            noise: 0,
+            wire: None,
+            trap: None,
+        }
+    }
+    //Soft enough to push through or throw through, but nothing shows on the other side
+    fn new_fungus() -> Tile {
+        Tile {
+            name: String::from("fungus"),
+            description: "A curtain of hanging fungus, you can't see through it.",
+            glyph: '"',
+            color: Palette::DARK_GREEN,
+            explored: false,
+            visible: false,
+            color_in_fov: Palette::LIGHT_GREEN,
+            blocks_movement: false,
+            blocks_sight: true,
+            blocks_projectiles: false,
+            light: 0,
+            slow: false,
+            noise: 0,
+            wire: None,
+            trap: None,
+        }
+    }
+    fn new_crystal() -> Tile {
+        Tile {
+            name: String::from("crystal"),
+            description: "A wall of clear crystal, you can see right through it.",
+            glyph: '*',
+            color: Palette::DARK_BLUE,
+            explored: false,
+            visible: false,
+            color_in_fov: Palette::PINK,
+            blocks_movement: true,
+            blocks_sight: false,
+            blocks_projectiles: true,
+            light: 0,
+            slow: false,
+            noise: 0,
+            wire: None,
+            trap: None,
+        }
+    }
+
+    //Bars you can see through, but nothing gets past
+    fn new_gate() -> Tile {
+        Tile {
+            name: String::from("gate"),
+            description: "A gate of rusty bars, it's moved by some mechanism.",
+            glyph: '+',
+            color: Palette::DARK_BLUE,
+            explored: false,
+            visible: false,
+            color_in_fov: Palette::ORANGE,
+            blocks_movement: true,
+            blocks_sight: false,
+            blocks_projectiles: true,
+            light: 0,
+            slow: false,
+            noise: 0,
+            wire: None,
+            trap: None,
+        }
+    }
+    //Swung out of the way until its wire closes it again
+    fn new_open_gate() -> Tile {
+        Tile {
+            name: String::from("open_gate"),
+            description: "An open gate, it's moved by some mechanism.",
+            glyph: '/',
+            color: Palette::DARK_BLUE,
+            explored: false,
+            visible: false,
+            color_in_fov: Palette::ORANGE,
+            blocks_movement: false,
+            blocks_sight: false,
+            blocks_projectiles: false,
+            light: 0,
+            slow: false,
+            noise: 0,
+            wire: None,
+            trap: None,
+        }
+    }
+    //Bumped to pull it
+    fn new_lever() -> Tile {
+        Tile {
+            name: String::from("lever"),
+            description: "A lever set in the rock, something is wired to it.",
+            glyph: 'X',
+            color: Palette::DARK_BLUE,
+            explored: false,
+            visible: false,
+            color_in_fov: Palette::YELLOW,
+            blocks_movement: true,
+            blocks_sight: false,
+            blocks_projectiles: true,
+            light: 0,
+            slow: false,
+            noise: 0,
+            wire: None,
+            trap: None,
+        }
+    }
+    //Pressed by the player stepping on it or a pebble landing on it
+    fn new_plate() -> Tile {
+        Tile {
+            name: String::from("plate"),
+            description: "A pressure plate, something is wired to it.",
+            glyph: '_',
+            color: Palette::DARK_BLUE,
+            explored: false,
+            visible: false,
+            color_in_fov: Palette::YELLOW,
+            blocks_movement: false,
+            blocks_sight: false,
+            blocks_projectiles: false,
+            light: 0,
+            slow: false,
+            noise: 0,
+            wire: None,
+            trap: None,
         }
     }
+
+    fn new_hidden_trap(trap: Trap) -> Tile {
+        Tile {
+            trap: Some(trap),
+            ..Tile::new_floor()
+        }
+    }
+    pub fn new_trap(trap: Trap) -> Tile {
+        Tile {
+            name: String::from(trap.name()),
+            description: trap.description(),
+            glyph: '^',
+            color: Palette::DARK_BLUE,
+            explored: false,
+            visible: false,
+            color_in_fov: Palette::RED,
+            blocks_movement: false,
+            blocks_sight: false,
+            blocks_projectiles: false,
+            light: 0,
+            slow: false,
+            noise: 0,
+            wire: None,
+            trap: Some(trap),
+        }
+    }
+    pub fn hidden_trap(&self) -> Option<Trap> {
+        self.trap.filter(|trap| self.name != trap.name())
+    }
+
+    //As drawn in vault files
+    pub fn from_glyph(glyph: char) -> Tile {
+        match glyph {
+            '#' => Tile::new_wall(),
+            ':' => Tile::new_moss(),
+            '~' => Tile::new_shallows(),
+            '=' => Tile::new_pool(),
+            ';' => Tile::new_chasm(),
+            '%' => Tile::new_rubble(),
+            'I' => Tile::new_stalagmite(),
+            '"' => Tile::new_fungus(),
+            '*' => Tile::new_crystal(),
+            '+' => Tile::new_gate(),
+            'X' => Tile::new_lever(),
+            '_' => Tile::new_plate(),
+            _ => Tile::new_floor(),
+        }
+    }
+
+    //Pebbles that land here are gone
+    pub fn swallows(&self) -> bool {
+        self.name == "shallows" || self.name == "pool" || self.name == "chasm"
+    }
 }
 
 pub const WINDOW_SIZE: Vector = Vector {
@@ -149,40 +513,116 @@ pub const WINDOW_SIZE: Vector = Vector {
     y: 768.0,
 };
 
-pub const GLYPHS: &str = "@#.x >_+Xo:|-";
+pub const GLYPHS: &str = "@#.x >_+Xo:|-c!=~;%I\"*/^d<&";
 pub const TILE_SIZE: Vector = Vector { x: 12.0, y: 12.0 };
-pub const MAP_SIZE: Vector = Vector { x: 57.0, y: 40.0 };
-
-pub fn compute_fov(map: &mut Map, player_pos: Vector) {
-    for dx in -1..2 {
-        for dy in -1..2 {
-            let future_x: i32 = player_pos.x as i32 + dx as i32;
-            let future_y: i32 = player_pos.y as i32 + dy as i32;
-            if future_x != 0
-                && future_x != MAP_SIZE.x as i32
-                && future_y != 0
-                && future_y != MAP_SIZE.y as i32
-            {
-                let line_to_point = get_line(player_pos, Vector::new(future_x, future_y));
-                for point in line_to_point.iter() {
-                    let tile = &mut map[point.x as usize][point.y as usize];
-                    tile.is_in_fov = true;
-                    if tile.blocks {
-                        break;
-                    }
+pub const MAP_SIZE: Vector = Vector { x: 120.0, y: 80.0 };
+pub const VIEWPORT_SIZE: Vector = Vector { x: 57.0, y: 40.0 };
+
+//Brightness of remembered tiles compared to visible ones
+const REMEMBERED_SHADE: f32 = 0.6;
+
+//Light of a moss patch on its own tile, fading by one per tile away from it
+pub const LIGHT_RADIUS: i32 = 3;
+
+pub fn distance(a: Vector, b: Vector) -> i32 {
+    let dx = (a.x - b.x).abs() as i32;
+    let dy = (a.y - b.y).abs() as i32;
+    dx.max(dy)
+}
+
+//For creatures, the player can also jump into chasms and may swim
+pub fn is_walkable(map: &Map, pos: Vector) -> bool {
+    is_in_bounds(map, pos) && {
+        let tile = &map[pos.x as usize][pos.y as usize];
+        !tile.blocks_movement && tile.name != "pool" && tile.name != "chasm"
+    }
+}
+
+pub fn map_size(map: &Map) -> Vector {
+    Vector::new(
+        map.len() as i32,
+        map.first().map_or(0, |col| col.len()) as i32,
+    )
+}
+
+//Only what the player sees from where they stand is visible, everything seen before stays explored
+//Returns how many tiles were seen for the first time
+pub fn compute_fov(map: &mut Map, player_pos: Vector, radius: i32) -> u32 {
+    map.iter_mut()
+        .flatten()
+        .for_each(|tile| tile.visible = false);
+    reveal(map, player_pos, radius)
+}
+
+//Rays to every cell on the edge of the square around the center, each stops at the first tile blocking sight
+//Marks the tiles as visible and explored, returns how many were seen for the first time
+pub fn reveal(map: &mut Map, center: Vector, radius: i32) -> u32 {
+    let mut explored = 0;
+    for dx in -radius..=radius {
+        for dy in -radius..=radius {
+            if dx.abs() != radius && dy.abs() != radius {
+                continue;
+            }
+            let target = center + Vector::new(dx, dy);
+            for point in get_line(center, target).iter() {
+                if !is_on_map(map, *point) {
+                    break;
+                }
+                let tile = &mut map[point.x as usize][point.y as usize];
+                tile.visible = true;
+                if !tile.explored {
+                    tile.explored = true;
+                    explored += 1;
+                }
+                if tile.blocks_sight {
+                    break;
                 }
             }
         }
     }
+    explored
+}
+
+//Within the torch light and with no wall in between
+pub fn is_visible(map: &Map, from: Vector, to: Vector, radius: i32) -> bool {
+    if distance(from, to) > radius {
+        return false;
+    }
+    let line = get_line(from, to);
+    line.iter().take(line.len().saturating_sub(1)).all(|point| {
+        is_on_map(map, *point) && !map[point.x as usize][point.y as usize].blocks_sight
+    })
 }
 
-pub fn generate() -> (Map, Vector) {
+//Unlike is_in_bounds this includes the outer walls
+fn is_on_map(map: &Map, pos: Vector) -> bool {
+    let size = map_size(map);
+    pos.x >= 0.0 && pos.x < size.x && pos.y >= 0.0 && pos.y < size.y
+}
+
+//The bottom of the cave, where the artifact lies
+pub const DEEPEST: u32 = 5;
+
+pub fn generate(rng: &mut StdRng) -> (Map, Vector) {
     use rand::distributions::{Distribution, Uniform};
-    use rand::prelude::*;
 
     const WALL_PERC: u32 = 30;
     const ITERATIONS: u32 = 5;
-    let mut rng = thread_rng();
+    const MOSS_PATCHES: u32 = 12;
+    const POOLS: u32 = 8;
+    const RUBBLE_HEAPS: u32 = 10;
+    const CHASMS: u32 = 4;
+    const FUNGUS_PATCHES: u32 = 8;
+    const CRYSTAL_VEINS: u32 = 6;
+    const STALAGMITES: u32 = 60;
+    const VAULTS: u32 = 2;
+    //Spots tried for each vault before leaving it out
+    const VAULT_TRIES: u32 = 20;
+    const TRAPS_PER_LEVEL: u32 = 20;
+    //Columns kept free of vaults at each side of the map
+    const VAULT_MARGIN: usize = 10;
+    //Chance for each floor tile in a cluster's radius to become part of it
+    const CLUSTER_PERC: u32 = 75;
     let die_range = Uniform::new_inclusive(1, 100);
 
     fn count_walls(pos: Vector, map: &Map) -> u32 {
@@ -202,13 +642,12 @@ pub fn generate() -> (Map, Vector) {
 
     let mut map: Vec<Vec<Tile>> =
         vec![vec![Tile::new_floor(); MAP_SIZE.y as usize]; MAP_SIZE.x as usize];
-    let mut first_floor = Vector::new(0, 0);
     for x in 0..map.len() {
         for y in 0..map[x].len() {
             if x == 0 || x == map.len() - 1 || y == 0 || y == map[x].len() - 1 {
                 map[x][y] = Tile::new_wall();
             } else {
-                let roll_die = die_range.sample(&mut rng);
+                let roll_die = die_range.sample(rng);
                 if roll_die <= WALL_PERC {
                     map[x][y] = Tile::new_wall();
                 }
@@ -244,40 +683,309 @@ pub fn generate() -> (Map, Vector) {
         }
     }
 
-    for (x, col) in map.iter().enumerate() {
-        for (y, tile) in col.iter().enumerate() {
-            if tile.name == "floor" {
-                first_floor = Vector::new(x as i32, y as i32)
+    let x_range = Uniform::new(1, map.len() - 1);
+    let y_range = Uniform::new(1, map[0].len() - 1);
+    for _i in 0..MOSS_PATCHES {
+        let (x, y) = (x_range.sample(rng), y_range.sample(rng));
+        if map[x][y].name == "floor" {
+            map[x][y] = Tile::new_moss();
+        }
+    }
+
+    //Ragged blobs around random spots, pools have deep water in the middle and shallows around it
+    //Crystal grows into the walls, everything else over the floor
+    let radius_range = Uniform::new_inclusive(2, 4);
+    let small_clusters = POOLS + RUBBLE_HEAPS;
+    let walls_from = small_clusters + CHASMS + FUNGUS_PATCHES;
+    for i in 0..walls_from + CRYSTAL_VEINS {
+        let (cx, cy) = (x_range.sample(rng) as i32, y_range.sample(rng) as i32);
+        let radius = if i < small_clusters {
+            radius_range.sample(rng)
+        } else {
+            radius_range.sample(rng) / 2
+        };
+        let grows_over = if i >= walls_from { "wall" } else { "floor" };
+        for dx in -radius..=radius {
+            for dy in -radius..=radius {
+                let (x, y) = (cx + dx, cy + dy);
+                let d2 = dx * dx + dy * dy;
+                if !is_in_bounds(&map, Vector::new(x, y)) || d2 > radius * radius {
+                    continue;
+                }
+                if map[x as usize][y as usize].name != grows_over
+                    || die_range.sample(rng) > CLUSTER_PERC
+                {
+                    continue;
+                }
+                map[x as usize][y as usize] = if i >= walls_from {
+                    Tile::new_crystal()
+                } else if i >= small_clusters + CHASMS {
+                    Tile::new_fungus()
+                } else if i >= small_clusters {
+                    Tile::new_chasm()
+                } else if i >= POOLS {
+                    Tile::new_rubble()
+                } else if d2 <= (radius - 2) * (radius - 2) {
+                    Tile::new_pool()
+                } else {
+                    Tile::new_shallows()
+                };
             }
         }
     }
 
-    (map, first_floor)
+    for _i in 0..STALAGMITES {
+        let (x, y) = (x_range.sample(rng), y_range.sample(rng));
+        if map[x][y].name == "floor" {
+            map[x][y] = Tile::new_stalagmite();
+        }
+    }
+
+    let trap_range = Uniform::new(0, TRAPS.len());
+    for _i in 0..TRAPS_PER_LEVEL {
+        let (x, y) = (x_range.sample(rng), y_range.sample(rng));
+        if map[x][y].name == "floor" {
+            map[x][y] = Tile::new_hidden_trap(TRAPS[trap_range.sample(rng)]);
+        }
+    }
+
+    //Away from the ends of the cave, where the stairs and the player go
+    let vaults = vault::bundled();
+    let vault_range = Uniform::new(0, vaults.len());
+    //Left, top, right and bottom of every vault stamped so far, so none cuts into another
+    let mut stamped: Vec<(usize, usize, usize, usize)> = vec![];
+    for wire in 0..VAULTS {
+        let vault = &vaults[vault_range.sample(rng)];
+        let x_range = Uniform::new(VAULT_MARGIN, map.len() - VAULT_MARGIN - vault.width());
+        let y_range = Uniform::new(1, map[0].len() - 1 - vault.height());
+        let spot = (0..VAULT_TRIES)
+            .map(|_| {
+                let (x, y) = (x_range.sample(rng), y_range.sample(rng));
+                (x, y, x + vault.width(), y + vault.height())
+            })
+            .find(|(left, top, right, bottom)| {
+                stamped.iter().all(|other| {
+                    *right <= other.0 || *left >= other.2 || *bottom <= other.1 || *top >= other.3
+                })
+            });
+        if let Some((x, y, right, bottom)) = spot {
+            stamp(&mut map, vault, Vector::new(x as i32, y as i32), wire);
+            stamped.push((x, y, right, bottom));
+        }
+    }
+    compute_light(&mut map);
+
+    //The player starts in the biggest open stretch of the cave, not in some sealed pocket
+    let is_clear = |map: &Map, pos: Vector| {
+        let tile = &map[pos.x as usize][pos.y as usize];
+        tile.name == "floor" && tile.trap.is_none()
+    };
+    let mut regions: Vec<Vec<Vec<Option<u32>>>> = vec![];
+    for x in 0..map.len() {
+        for y in 0..map[x].len() {
+            let pos = Vector::new(x as i32, y as i32);
+            let known = regions.iter().any(|steps| steps[x][y].is_some());
+            if is_clear(&map, pos) && !known {
+                regions.push(steps_from(&map, pos));
+            }
+        }
+    }
+    let size = |steps: &Vec<Vec<Option<u32>>>| steps.iter().flatten().flatten().count();
+    let region = regions.into_iter().max_by_key(|steps| size(steps));
+
+    //Down at the far end of the cave from where the player starts
+    let mut spawn = Vector::new(0, 0);
+    if let Some(region) = region {
+        for (x, col) in region.iter().enumerate() {
+            for (y, steps) in col.iter().enumerate() {
+                if steps.is_some() && is_clear(&map, Vector::new(x as i32, y as i32)) {
+                    spawn = Vector::new(x as i32, y as i32);
+                }
+            }
+        }
+        let steps = steps_from(&map, spawn);
+        let mut stairs = None;
+        let mut farthest = 0;
+        for (x, col) in steps.iter().enumerate() {
+            for (y, steps) in col.iter().enumerate() {
+                let pos = Vector::new(x as i32, y as i32);
+                if let Some(steps) = *steps {
+                    if steps > farthest && is_clear(&map, pos) {
+                        farthest = steps;
+                        stairs = Some(pos);
+                    }
+                }
+            }
+        }
+        if let Some(stairs) = stairs {
+            map[stairs.x as usize][stairs.y as usize] = Tile::new_stairs();
+        }
+    }
+
+    (map, spawn)
 }
 
-pub fn draw_map(window: &mut Window, map: &Vec<Vec<Tile>>, tileset: &mut HashMap<char, Image>) {
-    for (x, tile_col) in map.iter().enumerate() {
-        for (y, tile) in tile_col.iter().enumerate() {
-            if let Some(image) = tileset.get(&tile.glyph) {
-                let pos_px = Vector::new(x as i32, y as i32).times(TILE_SIZE);
-                window.draw(
-                    &Rectangle::new(pos_px, image.area().size()),
-                    Col(Palette::DARK_BLUE),
-                );
-                window.draw(
-                    &Rectangle::new(pos_px, image.area().size()),
-                    Blended(
-                        &image,
-                        if tile.is_in_fov {
-                            tile.color_in_fov
-                        } else {
-                            tile.color
-                        },
-                    ),
-                )
+//Steps a creature needs to reach every tile from start, none where it can't go
+pub fn steps_from(map: &Map, start: Vector) -> Vec<Vec<Option<u32>>> {
+    let mut steps = vec![vec![None; map[0].len()]; map.len()];
+    if !is_walkable(map, start) {
+        return steps;
+    }
+    steps[start.x as usize][start.y as usize] = Some(0);
+    let mut frontier = VecDeque::new();
+    frontier.push_back(start);
+    while let Some(pos) = frontier.pop_front() {
+        let next_steps = steps[pos.x as usize][pos.y as usize].unwrap() + 1;
+        for (dx, dy) in [(1, 0), (-1, 0), (0, 1), (0, -1)].iter() {
+            let next = pos + Vector::new(*dx, *dy);
+            if is_walkable(map, next) && steps[next.x as usize][next.y as usize].is_none() {
+                steps[next.x as usize][next.y as usize] = Some(next_steps);
+                frontier.push_back(next);
+            }
+        }
+    }
+    steps
+}
+
+//The way back up where the player arrives from above, the exit on the first level
+//The bottom level has no way down, returns where the stairs down are or would have been
+pub fn connect(map: &mut Map, spawn: Vector, depth: u32) -> Option<Vector> {
+    map[spawn.x as usize][spawn.y as usize] = if depth == 1 {
+        Tile::new_exit()
+    } else {
+        Tile::new_upstairs()
+    };
+    let stairs = map.iter().enumerate().find_map(|(x, col)| {
+        col.iter()
+            .position(|tile| tile.name == "stairs")
+            .map(|y| Vector::new(x as i32, y as i32))
+    })?;
+    if depth >= DEEPEST {
+        map[stairs.x as usize][stairs.y as usize] = Tile::new_floor();
+    }
+    Some(stairs)
+}
+
+//Every gate, lever and plate of the vault gets the same wire
+fn stamp(map: &mut Map, vault: &Vault, pos: Vector, wire: u32) {
+    for (dy, row) in vault.rows.iter().enumerate() {
+        for (dx, glyph) in row.iter().enumerate() {
+            let mut tile = Tile::from_glyph(*glyph);
+            if tile.name == "gate" || tile.name == "lever" || tile.name == "plate" {
+                tile.wire = Some(wire);
+            }
+            map[pos.x as usize + dx][pos.y as usize + dy] = tile;
+        }
+    }
+}
+
+//Opens or closes every gate on the wire, except where something stands in the way
+//Returns how many gates moved
+pub fn set_gates(map: &mut Map, wire: u32, open: bool, is_free: impl Fn(Vector) -> bool) -> u32 {
+    let mut moved = 0;
+    for (x, col) in map.iter_mut().enumerate() {
+        for (y, tile) in col.iter_mut().enumerate() {
+            if !is_gate(tile) || tile.wire != Some(wire) || tile.blocks_movement != open {
+                continue;
+            }
+            if !open && !is_free(Vector::new(x as i32, y as i32)) {
+                continue;
+            }
+            let (explored, visible) = (tile.explored, tile.visible);
+            *tile = if open {
+                Tile::new_open_gate()
+            } else {
+                Tile::new_gate()
+            };
+            tile.wire = Some(wire);
+            tile.explored = explored;
+            tile.visible = visible;
+            moved += 1;
+        }
+    }
+    moved
+}
+
+//Open or closed
+fn is_gate(tile: &Tile) -> bool {
+    tile.name == "gate" || tile.name == "open_gate"
+}
+
+//Whether the gates on the wire are open, going by the first one
+pub fn gates_open(map: &Map, wire: u32) -> bool {
+    map.iter()
+        .flatten()
+        .find(|tile| is_gate(tile) && tile.wire == Some(wire))
+        .is_some_and(|tile| !tile.blocks_movement)
+}
+
+pub fn compute_light(map: &mut Map) {
+    let mut sources = vec![];
+    for (x, col) in map.iter_mut().enumerate() {
+        for (y, tile) in col.iter_mut().enumerate() {
+            tile.light = 0;
+            if tile.name == "moss" {
+                sources.push(Vector::new(x as i32, y as i32));
             }
         }
     }
+
+    for source in sources {
+        for dx in -LIGHT_RADIUS + 1..LIGHT_RADIUS {
+            for dy in -LIGHT_RADIUS + 1..LIGHT_RADIUS {
+                let target = source + Vector::new(dx, dy);
+                if !is_in_bounds(map, target) {
+                    continue;
+                }
+                let reaches = get_line(source, target)
+                    .iter()
+                    .skip(1)
+                    .rev()
+                    .skip(1)
+                    .all(|point| !map[point.x as usize][point.y as usize].blocks_sight);
+                if reaches {
+                    let tile = &mut map[target.x as usize][target.y as usize];
+                    tile.light = tile.light.max(LIGHT_RADIUS - distance(source, target));
+                }
+            }
+        }
+    }
+}
+
+//Explored tiles out of sight are drawn darker
+fn remembered(color: Color) -> Color {
+    Color {
+        r: color.r * REMEMBERED_SHADE,
+        g: color.g * REMEMBERED_SHADE,
+        b: color.b * REMEMBERED_SHADE,
+        a: color.a,
+    }
+}
+
+pub fn draw_map(renderer: &mut dyn Renderer, map: &Map, camera: &Camera, theme: &Theme) {
+    let size = map_size(map);
+    for sx in 0..camera.size.x as i32 {
+        for sy in 0..camera.size.y as i32 {
+            let pos = camera.offset + Vector::new(sx, sy);
+            if pos.x >= size.x || pos.y >= size.y {
+                continue;
+            }
+            let tile = &map[pos.x as usize][pos.y as usize];
+            renderer.put_tile(
+                Vector::new(sx, sy),
+                &tile.name,
+                tile.glyph,
+                if tile.visible {
+                    theme.lit(&tile.name, tile.color_in_fov)
+                } else if tile.explored {
+                    remembered(theme.lit(&tile.name, tile.color_in_fov))
+                } else {
+                    theme.unlit(&tile.name, tile.color)
+                },
+                Some(theme.background),
+            );
+        }
+    }
 }
 
 //Bresenham's from http://www.roguebasin.com/index.php?title=Bresenham%27s_Line_Algorithm
@@ -333,9 +1041,7 @@ pub fn get_line(a: Vector, b: Vector) -> Vec<Vector> {
     points
 }
 
-pub fn is_in_bounds(pos: Vector) -> bool {
-    return pos.x != 0.0
-        && pos.x != MAP_SIZE.x
-        && pos.y != 0.0
-        && pos.y != MAP_SIZE.y;
+pub fn is_in_bounds(map: &Map, pos: Vector) -> bool {
+    let size = map_size(map);
+    pos.x > 0.0 && pos.x < size.x - 1.0 && pos.y > 0.0 && pos.y < size.y - 1.0
 }
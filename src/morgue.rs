@@ -0,0 +1,100 @@
+use crate::game::Game;
+use crate::map::map_size;
+use crate::torch;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+//How many of the last messages end up in the file
+const MESSAGES: usize = 10;
+
+//Plain-text summary of a finished run, meant to be pasted into a chat
+pub fn dump(game: &Game) -> String {
+    let data = &game.ui_data;
+    let mut text = String::new();
+    text.push_str(&format!("Seed: {}\n", game.seed));
+    if game.has_escaped() {
+        text.push_str("Escaped with the artifact\n");
+    } else {
+        text.push_str(&format!("Killed by {}\n", game.cause_of_death()));
+    }
+    text.push_str(&format!("Turns: {}\n", data.turn));
+    text.push_str(&format!("Depth: {}\n", data.depth));
+    text.push_str(&format!("Kills: {}\n", data.kills));
+    let mut inventory = format!(
+        "pebbles x{}, torch fuel {}/{}",
+        data.pebbles,
+        data.fuel,
+        torch::MAX_FUEL
+    );
+    if data.has_artifact {
+        inventory.push_str(", the artifact");
+    }
+    text.push_str(&format!("Inventory: {}\n", inventory));
+
+    text.push_str("\nLast messages:\n");
+    let skip = game.message_log.log.len().saturating_sub(MESSAGES);
+    for message in game.message_log.log.iter().skip(skip) {
+        text.push_str(&format!("  {}\n", message));
+    }
+
+    text.push_str("\nMap:\n");
+    text.push_str(&map_text(game));
+    text
+}
+
+//Explored tiles and the entities the player could see, everything else left blank
+fn map_text(game: &Game) -> String {
+    let size = map_size(&game.map);
+    let mut rows: Vec<Vec<char>> = (0..size.y as usize)
+        .map(|y| {
+            (0..size.x as usize)
+                .map(|x| {
+                    let tile = &game.map[x][y];
+                    match (tile.explored, tile.glyph) {
+                        //Floor is drawn blank on screen, but should stand out from the unknown here
+                        (true, ' ') => '.',
+                        (true, glyph) => glyph,
+                        (false, _) => ' ',
+                    }
+                })
+                .collect()
+        })
+        .collect();
+
+    let mut visible: Vec<_> = game
+        .entities
+        .values()
+        .filter(|entity| entity.is_in_fov)
+        .collect();
+    visible.sort_by_key(|entity| entity.z);
+    for entity in visible {
+        rows[entity.pos.y as usize][entity.pos.x as usize] = entity.glyph;
+    }
+
+    //Cropped to the explored part, the rest of the cave is blank anyway
+    let is_used = |row: &Vec<char>| row.iter().any(|glyph| *glyph != ' ');
+    let top = rows.iter().position(is_used).unwrap_or(0);
+    let bottom = rows.iter().rposition(is_used).map_or(0, |y| y + 1);
+    let left = rows
+        .iter()
+        .filter_map(|row| row.iter().position(|glyph| *glyph != ' '))
+        .min()
+        .unwrap_or(0);
+
+    let mut text = String::new();
+    for row in rows[top..bottom.max(top)].iter() {
+        let line: String = row.iter().skip(left).collect();
+        text.push_str(line.trim_end());
+        text.push('\n');
+    }
+    text
+}
+
+//Writes the dump into dir, named after the seed and the turn of death
+pub fn save(dir: &Path, game: &Game) -> io::Result<PathBuf> {
+    fs::create_dir_all(dir)?;
+    let path = dir.join(format!("morgue_{}_{}.txt", game.seed, game.ui_data.turn));
+    fs::write(&path, dump(game))?;
+    Ok(path)
+}
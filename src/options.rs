@@ -0,0 +1,56 @@
+use crate::animation::Speed;
+use std::path::PathBuf;
+
+//Command line options shared by every frontend
+pub struct Options {
+    pub seed: Option<u64>,
+    pub replay: Option<PathBuf>,
+    pub record: PathBuf,
+    pub morgue: PathBuf,
+    //Sprite sheet mapping inside static/, the font is used when there is none
+    pub tileset: Option<String>,
+    pub themes: PathBuf,
+    pub animations: Speed,
+}
+
+impl Options {
+    //Paths are resolved against the launch directory, quicksilver later moves into static/
+    pub fn from_args() -> Options {
+        let launch_dir = std::env::current_dir().unwrap_or_default();
+        let mut options = Options {
+            seed: None,
+            replay: None,
+            record: launch_dir.join("last_run.replay"),
+            morgue: launch_dir.join("morgue"),
+            tileset: None,
+            themes: launch_dir.join("static").join("themes"),
+            animations: Speed::Normal,
+        };
+
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--seed" => options.seed = args.next().and_then(|seed| seed.parse().ok()),
+                "--replay" => options.replay = args.next().map(|path| launch_dir.join(path)),
+                "--record" => {
+                    if let Some(path) = args.next() {
+                        options.record = launch_dir.join(path);
+                    }
+                }
+                "--morgue" => {
+                    if let Some(path) = args.next() {
+                        options.morgue = launch_dir.join(path);
+                    }
+                }
+                "--tileset" => options.tileset = args.next(),
+                "--animations" => match args.next().as_deref().and_then(Speed::from_name) {
+                    Some(speed) => options.animations = speed,
+                    None => eprintln!("--animations takes normal, fast or off"),
+                },
+                _ => eprintln!("Unknown argument: {}", arg),
+            }
+        }
+
+        options
+    }
+}
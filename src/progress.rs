@@ -0,0 +1,74 @@
+//Experience from exploring, collecting and fighting, and the perks picked on each level-up
+
+//Experience for each newly explored tile, item picked up, kill and level descended
+pub const EXPLORE_XP: u32 = 1;
+pub const PICKUP_XP: u32 = 2;
+pub const KILL_XP: u32 = 10;
+pub const DESCEND_XP: u32 = 20;
+
+//Each level also raises the player's max hp, and heals that much
+pub const HP_PER_LEVEL: i32 = 1;
+
+//What one rank of each perk adds
+pub const THROW_BONUS: i32 = 2;
+pub const QUIET_BONUS: i32 = 1;
+pub const LIGHT_BONUS: u32 = 1;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Perk {
+    //Throws reach further
+    ThrowRange,
+    //Footsteps make less noise
+    QuietSteps,
+    //The torch lights a wider radius
+    WideLight,
+    //Deep water can be crossed
+    Swimming,
+}
+
+pub const PERKS: [Perk; 4] = [
+    Perk::ThrowRange,
+    Perk::QuietSteps,
+    Perk::WideLight,
+    Perk::Swimming,
+];
+
+impl Perk {
+    //As written in replay files
+    pub fn name(self) -> &'static str {
+        match self {
+            Perk::ThrowRange => "throw",
+            Perk::QuietSteps => "quiet",
+            Perk::WideLight => "light",
+            Perk::Swimming => "swim",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Perk> {
+        PERKS.iter().copied().find(|perk| perk.name() == name)
+    }
+
+    //Shown on the level-up screen
+    pub fn title(self) -> &'static str {
+        match self {
+            Perk::ThrowRange => "Strong arm: throw pebbles further",
+            Perk::QuietSteps => "Soft soles: quieter footsteps",
+            Perk::WideLight => "Wide wick: your torch lights further",
+            Perk::Swimming => "Swimmer: cross deep water",
+        }
+    }
+
+    //Swimming only needs learning once
+    pub fn is_repeatable(self) -> bool {
+        self != Perk::Swimming
+    }
+
+    pub fn from_title(title: &str) -> Option<Perk> {
+        PERKS.iter().copied().find(|perk| perk.title() == title)
+    }
+}
+
+//Experience needed to go from this level to the next
+pub fn xp_to_next(level: u32) -> u32 {
+    50 * level
+}
@@ -0,0 +1,306 @@
+use crate::data;
+use crate::map::{GLYPHS, TILE_SIZE};
+use quicksilver::{
+    geom::{Rectangle, Shape, Transform, Vector},
+    graphics::{Background::Blended, Background::Col, Color, Font, FontStyle, Image},
+    lifecycle::Window,
+    Result,
+};
+use std::collections::HashMap;
+use std::io;
+
+//Whole window measured in cells
+pub const SCREEN_SIZE: Vector = Vector { x: 85.0, y: 64.0 };
+
+//Everything on screen is a glyph or a line of text placed on the cell grid
+pub trait Renderer {
+    fn put_glyph(&mut self, cell: Vector, glyph: char, fg: Color, bg: Option<Color>);
+    fn put_text(&mut self, cell: Vector, text: &str, fg: Color);
+
+    //A map tile or entity, id is its name so a sprite sheet can tell them apart
+    fn put_tile(&mut self, cell: Vector, _id: &str, glyph: char, fg: Color, bg: Option<Color>) {
+        self.put_glyph(cell, glyph, fg, bg);
+    }
+}
+
+//Which cell of a sprite sheet draws each tile or entity name, or each glyph
+pub struct SheetMapping {
+    pub image: String,
+    pub tile_size: Vector,
+    pub cells: HashMap<String, Vector>,
+}
+
+impl SheetMapping {
+    //"image <file>", "size <w> <h>", then "<name or glyph> <column> <row>" lines, // for comments
+    pub fn parse(text: &str) -> io::Result<SheetMapping> {
+        let invalid = |line: &str| data::invalid_line("sprite sheet", line);
+        let numbers = |words: &[&str]| -> Option<Vector> {
+            match words {
+                [x, y] => Some(Vector::new(x.parse::<u32>().ok()?, y.parse::<u32>().ok()?)),
+                _ => None,
+            }
+        };
+
+        let mut image = None;
+        let mut tile_size = None;
+        let mut cells = HashMap::new();
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with("//") {
+                continue;
+            }
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words[..] {
+                ["image", file] => image = Some(file.to_string()),
+                ["size", ..] => {
+                    tile_size = Some(numbers(&words[1..]).ok_or_else(|| invalid(line))?)
+                }
+                [key, ..] => {
+                    let cell = numbers(&words[1..]).ok_or_else(|| invalid(line))?;
+                    cells.insert(key.to_string(), cell);
+                }
+                [] => {}
+            }
+        }
+
+        Ok(SheetMapping {
+            image: image.ok_or_else(|| invalid("no image line"))?,
+            tile_size: tile_size.ok_or_else(|| invalid("no size line"))?,
+            cells,
+        })
+    }
+
+    //A cell past the edge of the sheet would draw whatever the graphics driver makes of it
+    pub fn check_fits(&self, sheet_size: Vector) -> io::Result<()> {
+        for (key, cell) in self.cells.iter() {
+            let corner = (*cell + Vector::new(1, 1)).times(self.tile_size);
+            if corner.x > sheet_size.x || corner.y > sheet_size.y {
+                return Err(data::invalid(&format!(
+                    "Sprite sheet cell outside the image: {}",
+                    key
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+pub struct Tileset {
+    tiles: HashMap<char, Image>,
+    //Sprite sheet cells by name or glyph, drawn instead of the font where they exist
+    sprites: HashMap<String, Image>,
+    font: Font,
+    //Rendered text from this frame and the last, anything older is dropped
+    //The side panel and the log change every turn, so keeping everything would grow forever
+    texts: HashMap<String, Image>,
+    last_texts: HashMap<String, Image>,
+}
+
+impl Tileset {
+    pub fn from_font(font: Font) -> Result<Tileset> {
+        let rendered = font.render(GLYPHS, &FontStyle::new(TILE_SIZE.y, Color::WHITE))?;
+
+        let mut tiles = HashMap::new();
+        for (index, glyph) in GLYPHS.chars().enumerate() {
+            let pos = (index as i32 * TILE_SIZE.x as i32, 0);
+            tiles.insert(glyph, rendered.subimage(Rectangle::new(pos, TILE_SIZE)));
+        }
+
+        Ok(Tileset {
+            tiles,
+            sprites: HashMap::new(),
+            font,
+            texts: HashMap::new(),
+            last_texts: HashMap::new(),
+        })
+    }
+
+    //The font still covers every glyph the sheet has no cell for
+    pub fn from_sheet(font: Font, mapping: SheetMapping, sheet: Image) -> Result<Tileset> {
+        mapping.check_fits(sheet.area().size())?;
+        let mut tileset = Tileset::from_font(font)?;
+        for (key, cell) in mapping.cells {
+            let area = Rectangle::new(cell.times(mapping.tile_size), mapping.tile_size);
+            tileset.sprites.insert(key, sheet.subimage(area));
+        }
+        Ok(tileset)
+    }
+
+    fn image(&self, id: &str, glyph: char) -> Option<&Image> {
+        self.sprites
+            .get(id)
+            .or_else(|| self.sprites.get(glyph.encode_utf8(&mut [0; 4]) as &str))
+            .or_else(|| self.tiles.get(&glyph))
+    }
+
+    fn start_frame(&mut self) {
+        self.last_texts = std::mem::take(&mut self.texts);
+    }
+
+    //Text is rendered white once and tinted when drawn, for as long as it stays on screen
+    fn text(&mut self, text: &str) -> Option<&Image> {
+        if !self.texts.contains_key(text) {
+            let image = match self.last_texts.remove(text) {
+                Some(image) => image,
+                None => self
+                    .font
+                    .render(text, &FontStyle::new(TILE_SIZE.y, Color::WHITE))
+                    .ok()?,
+            };
+            self.texts.insert(text.to_string(), image);
+        }
+        self.texts.get(text)
+    }
+}
+
+pub struct QuicksilverRenderer<'a> {
+    window: &'a mut Window,
+    tileset: &'a mut Tileset,
+    //Draw order, quicksilver otherwise batches plain colours under every image
+    z: i32,
+}
+
+impl<'a> QuicksilverRenderer<'a> {
+    //One renderer per frame
+    pub fn new(window: &'a mut Window, tileset: &'a mut Tileset) -> QuicksilverRenderer<'a> {
+        tileset.start_frame();
+        QuicksilverRenderer {
+            window,
+            tileset,
+            z: 0,
+        }
+    }
+}
+
+impl<'a> Renderer for QuicksilverRenderer<'a> {
+    fn put_glyph(&mut self, cell: Vector, glyph: char, fg: Color, bg: Option<Color>) {
+        self.put_tile(cell, "", glyph, fg, bg);
+    }
+
+    fn put_text(&mut self, cell: Vector, text: &str, fg: Color) {
+        self.z += 1;
+        if let Some(image) = self.tileset.text(text) {
+            let area = image.area().translate(cell.times(TILE_SIZE));
+            self.window
+                .draw_ex(&area, Blended(image, fg), Transform::IDENTITY, self.z);
+        }
+    }
+
+    //Sprites are tinted like glyphs, so sheets should be drawn in white
+    fn put_tile(&mut self, cell: Vector, id: &str, glyph: char, fg: Color, bg: Option<Color>) {
+        self.z += 1;
+        let area = Rectangle::new(cell.times(TILE_SIZE), TILE_SIZE);
+        if let Some(bg) = bg {
+            self.window
+                .draw_ex(&area, Col(bg), Transform::IDENTITY, self.z);
+        }
+        if let Some(image) = self.tileset.image(id, glyph) {
+            self.window
+                .draw_ex(&area, Blended(image, fg), Transform::IDENTITY, self.z);
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Cell {
+    pub glyph: char,
+    pub fg: Color,
+    pub bg: Option<Color>,
+}
+
+impl Cell {
+    const EMPTY: Cell = Cell {
+        glyph: ' ',
+        fg: Color::WHITE,
+        bg: None,
+    };
+}
+
+//In-memory character grid, used for text snapshots and terminal output
+pub struct GridRenderer {
+    pub cells: Vec<Vec<Cell>>,
+}
+
+impl GridRenderer {
+    pub fn new(size: Vector) -> GridRenderer {
+        GridRenderer {
+            cells: vec![vec![Cell::EMPTY; size.x as usize]; size.y as usize],
+        }
+    }
+
+    fn cell_mut(&mut self, cell: Vector) -> Option<&mut Cell> {
+        if cell.x < 0.0 || cell.y < 0.0 {
+            return None;
+        }
+        self.cells
+            .get_mut(cell.y as usize)
+            .and_then(|row| row.get_mut(cell.x as usize))
+    }
+
+    //One line per row, trailing blanks trimmed
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for row in self.cells.iter() {
+            let line: String = row.iter().map(|cell| cell.glyph).collect();
+            text.push_str(line.trim_end());
+            text.push('\n');
+        }
+        text
+    }
+}
+
+impl Renderer for GridRenderer {
+    fn put_glyph(&mut self, cell: Vector, glyph: char, fg: Color, bg: Option<Color>) {
+        if let Some(target) = self.cell_mut(cell) {
+            target.glyph = glyph;
+            target.fg = fg;
+            if bg.is_some() {
+                target.bg = bg;
+            }
+        }
+    }
+
+    fn put_text(&mut self, cell: Vector, text: &str, fg: Color) {
+        for (index, glyph) in text.chars().enumerate() {
+            let pos = cell + Vector::new(index as i32, 0);
+            if let Some(target) = self.cell_mut(pos) {
+                target.glyph = glyph;
+                target.fg = fg;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAPPING: &str = "//Comment\nimage sprites.png\nsize 12 12\nplayer 0 0\no 6 1";
+
+    #[test]
+    fn mapping_parses() {
+        let mapping = SheetMapping::parse(MAPPING).unwrap();
+        assert_eq!(mapping.image, "sprites.png");
+        assert_eq!(mapping.tile_size, Vector::new(12, 12));
+        assert_eq!(mapping.cells["player"], Vector::new(0, 0));
+        assert_eq!(mapping.cells["o"], Vector::new(6, 1));
+    }
+
+    #[test]
+    fn malformed_lines_fail() {
+        assert!(SheetMapping::parse("image a.png\nsize 12\nplayer 0 0").is_err());
+        assert!(SheetMapping::parse("image a.png\nsize 12 12\nplayer 0").is_err());
+        assert!(SheetMapping::parse("image a.png\nsize 12 12\nplayer x 0").is_err());
+        assert!(SheetMapping::parse("image a.png\nsize 12 12\nplayer -1 0").is_err());
+        assert!(SheetMapping::parse("size 12 12\nplayer 0 0").is_err());
+        assert!(SheetMapping::parse("image a.png\nplayer 0 0").is_err());
+    }
+
+    #[test]
+    fn cells_outside_the_sheet_fail() {
+        let mapping = SheetMapping::parse(MAPPING).unwrap();
+        assert!(mapping.check_fits(Vector::new(84, 24)).is_ok());
+        assert!(mapping.check_fits(Vector::new(72, 24)).is_err());
+        assert!(mapping.check_fits(Vector::new(84, 12)).is_err());
+    }
+}
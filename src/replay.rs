@@ -0,0 +1,312 @@
+use crate::data;
+use crate::options::Options;
+use crate::progress::Perk;
+use quicksilver::geom::Vector;
+use rand::{thread_rng, Rng};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+//Everything the player can commit to, replaying these from the same seed rebuilds the run
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    Move(i32, i32),
+    Wait,
+    //Tells the dog to stay, or to follow again
+    Stay,
+    Search,
+    Sneak,
+    Throw,
+    Fire,
+    Cancel,
+    Restart,
+    Perk(Perk),
+}
+
+impl Action {
+    pub fn direction(self) -> Vector {
+        match self {
+            Action::Move(dx, dy) => Vector::new(dx, dy),
+            _ => Vector::ZERO,
+        }
+    }
+
+    fn to_line(self) -> String {
+        match self {
+            Action::Move(dx, dy) => format!("move {} {}", dx, dy),
+            Action::Wait => String::from("wait"),
+            Action::Stay => String::from("stay"),
+            Action::Search => String::from("search"),
+            Action::Sneak => String::from("sneak"),
+            Action::Throw => String::from("throw"),
+            Action::Fire => String::from("fire"),
+            Action::Cancel => String::from("cancel"),
+            Action::Restart => String::from("restart"),
+            Action::Perk(perk) => format!("perk {}", perk.name()),
+        }
+    }
+
+    fn from_line(line: &str) -> Option<Action> {
+        let mut words = line.split_whitespace();
+        match words.next()? {
+            "move" => {
+                let dx = words.next()?.parse().ok()?;
+                let dy = words.next()?.parse().ok()?;
+                Some(Action::Move(dx, dy))
+            }
+            "wait" => Some(Action::Wait),
+            "stay" => Some(Action::Stay),
+            "search" => Some(Action::Search),
+            "sneak" => Some(Action::Sneak),
+            "throw" => Some(Action::Throw),
+            "fire" => Some(Action::Fire),
+            "cancel" => Some(Action::Cancel),
+            "restart" => Some(Action::Restart),
+            "perk" => Some(Action::Perk(Perk::from_name(words.next()?)?)),
+            _ => None,
+        }
+    }
+}
+
+pub struct Replay {
+    pub seed: u64,
+    pub actions: Vec<Action>,
+}
+
+impl Replay {
+    pub fn load(path: &Path) -> io::Result<Replay> {
+        let invalid = |line: &str| data::invalid_line("replay", line);
+
+        let mut lines = BufReader::new(File::open(path)?).lines();
+        let first = lines.next().unwrap_or_else(|| Ok(String::new()))?;
+        let seed = first
+            .strip_prefix("seed ")
+            .and_then(|seed| seed.trim().parse().ok())
+            .ok_or_else(|| invalid(&first))?;
+
+        let mut actions = vec![];
+        for line in lines {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            actions.push(Action::from_line(&line).ok_or_else(|| invalid(&line))?);
+        }
+
+        Ok(Replay { seed, actions })
+    }
+}
+
+//Where a run's actions come from: a replay file, or the player while being recorded
+pub struct Session {
+    pub seed: u64,
+    pub playback: Option<Playback>,
+    recorder: Option<Recorder>,
+    record_path: PathBuf,
+}
+
+impl Session {
+    pub fn start(options: &Options) -> io::Result<Session> {
+        if let Some(path) = &options.replay {
+            let replay = Replay::load(path)?;
+            return Ok(Session {
+                seed: replay.seed,
+                playback: Some(Playback::new(replay)),
+                recorder: None,
+                record_path: options.record.clone(),
+            });
+        }
+
+        Ok(Session {
+            seed: options.seed.unwrap_or_else(|| thread_rng().gen()),
+            playback: None,
+            recorder: None,
+            record_path: options.record.clone(),
+        })
+    }
+
+    //Nothing is recorded until a run starts, each new run replaces the last recording
+    pub fn record_run(&mut self, seed: u64) {
+        self.seed = seed;
+        self.recorder = Recorder::create(&self.record_path, seed)
+            .map_err(|err| eprintln!("Could not record this run: {}", err))
+            .ok();
+    }
+
+    pub fn commit(&mut self, action: Action) {
+        if let Some(recorder) = &mut self.recorder {
+            recorder.record(action);
+        }
+    }
+}
+
+//Appends every committed action to the replay file as it happens, so a crash still leaves a usable replay
+pub struct Recorder {
+    file: File,
+}
+
+impl Recorder {
+    pub fn create(path: &Path, seed: u64) -> io::Result<Recorder> {
+        let mut file = File::create(path)?;
+        writeln!(file, "seed {}", seed)?;
+        Ok(Recorder { file })
+    }
+
+    pub fn record(&mut self, action: Action) {
+        if let Err(err) = writeln!(self.file, "{}", action.to_line()) {
+            eprintln!("Could not record action: {}", err);
+        }
+    }
+}
+
+//Updates to wait between two actions at each playback speed
+const SPEEDS: [u32; 7] = [60, 30, 15, 8, 4, 2, 1];
+
+pub struct Playback {
+    actions: Vec<Action>,
+    next: usize,
+    pub paused: bool,
+    speed: usize,
+    wait: u32,
+    announced_end: bool,
+}
+
+impl Playback {
+    pub fn new(replay: Replay) -> Playback {
+        Playback {
+            actions: replay.actions,
+            next: 0,
+            paused: false,
+            speed: 2,
+            wait: 0,
+            announced_end: false,
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.next >= self.actions.len()
+    }
+
+    //True only on the first update after the last action was played
+    pub fn just_finished(&mut self) -> bool {
+        if self.is_finished() && !self.announced_end {
+            self.announced_end = true;
+            return true;
+        }
+        false
+    }
+
+    pub fn faster(&mut self) {
+        self.speed = (self.speed + 1).min(SPEEDS.len() - 1);
+    }
+
+    pub fn slower(&mut self) {
+        self.speed = self.speed.saturating_sub(1);
+    }
+
+    //Next action to apply this update, if it's time for one
+    pub fn tick(&mut self) -> Option<Action> {
+        if self.paused || self.is_finished() {
+            return None;
+        }
+        self.wait += 1;
+        if self.wait < SPEEDS[self.speed] {
+            return None;
+        }
+        self.wait = 0;
+        self.step()
+    }
+
+    pub fn step(&mut self) -> Option<Action> {
+        let action = self.actions.get(self.next).copied();
+        self.next += 1;
+        action
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::Game;
+    use crate::morgue;
+    use crate::progress::PERKS;
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    fn every_action() -> Vec<Action> {
+        let mut actions = vec![
+            Action::Move(1, 0),
+            Action::Move(0, -1),
+            Action::Wait,
+            Action::Stay,
+            Action::Search,
+            Action::Sneak,
+            Action::Throw,
+            Action::Fire,
+            Action::Cancel,
+            Action::Restart,
+        ];
+        actions.extend(PERKS.iter().map(|perk| Action::Perk(*perk)));
+        actions
+    }
+
+    #[test]
+    fn lines_round_trip() {
+        for action in every_action() {
+            assert_eq!(Action::from_line(&action.to_line()), Some(action));
+        }
+        assert_eq!(Action::from_line("jump"), None);
+        assert_eq!(Action::from_line("move 1"), None);
+    }
+
+    #[test]
+    fn recorded_file_loads_back() {
+        let path = env::temp_dir().join(format!("rl_2019_replay_{}.txt", process::id()));
+        let actions = every_action();
+        {
+            let mut recorder = Recorder::create(&path, 77).unwrap();
+            for action in actions.iter() {
+                recorder.record(*action);
+            }
+        }
+        let replay = Replay::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(replay.seed, 77);
+        assert_eq!(replay.actions, actions);
+    }
+
+    //Same seed and same actions end in the same run, which is all a replay relies on
+    #[test]
+    fn replays_are_deterministic() {
+        let actions = [
+            Action::Move(1, 0),
+            Action::Wait,
+            Action::Search,
+            Action::Move(0, 1),
+            Action::Sneak,
+            Action::Move(-1, 0),
+            Action::Stay,
+            Action::Wait,
+            Action::Stay,
+            Action::Move(0, -1),
+        ];
+        let play = || {
+            let mut game = Game::new(5);
+            for _ in 0..10 {
+                for action in actions.iter() {
+                    game.apply(*action);
+                    if game.is_choosing_perk() {
+                        game.apply(Action::Perk(PERKS[0]));
+                    }
+                }
+            }
+            game
+        };
+        let (first, second) = (play(), play());
+        assert_eq!(first.player_pos(), second.player_pos());
+        assert_eq!(first.ui_data.turn, second.ui_data.turn);
+        assert_eq!(first.message_log.log, second.message_log.log);
+        assert_eq!(morgue::dump(&first), morgue::dump(&second));
+    }
+}
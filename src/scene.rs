@@ -0,0 +1,684 @@
+use crate::game::Game;
+use crate::progress::{Perk, PERKS};
+use crate::render::Renderer;
+use crate::replay::Action;
+use crate::score;
+use crate::ui;
+use quicksilver::geom::Vector;
+use rand::{thread_rng, Rng};
+
+//Keys as the scenes see them, each frontend translates its own
+#[derive(Clone, Copy, PartialEq)]
+pub enum Key {
+    Up,
+    Down,
+    Left,
+    Right,
+    Enter,
+    Escape,
+    Char(char),
+}
+
+//What the frontend has to do, scenes can't reach the window or the replay file
+pub enum Command {
+    Act(Action),
+    NewGame(u64),
+    Quit,
+}
+
+pub enum Transition {
+    None,
+    Push(Box<dyn Scene>),
+    Pop,
+    //Empties the stack, e.g. when going back to the title
+    Reset(Box<dyn Scene>),
+    Command(Command),
+}
+
+pub trait Scene {
+    fn handle(&mut self, key: Key, game: &mut Game) -> Transition;
+
+    //Called every frame, so scenes can follow what actions did to the game, even in replays
+    fn update(&mut self, _game: &Game) -> Transition {
+        Transition::None
+    }
+
+    fn draw(&self, game: &Game, renderer: &mut dyn Renderer, screen: Vector);
+
+    //Overlays are drawn on top of the scene below them
+    fn is_overlay(&self) -> bool {
+        false
+    }
+}
+
+pub struct SceneStack {
+    scenes: Vec<Box<dyn Scene>>,
+}
+
+impl SceneStack {
+    pub fn new(first: Box<dyn Scene>) -> SceneStack {
+        SceneStack {
+            scenes: vec![first],
+        }
+    }
+
+    //Starts on the title, or straight in the game when watching a replay
+    pub fn title() -> SceneStack {
+        SceneStack::new(Box::new(Title::new()))
+    }
+
+    pub fn playing() -> SceneStack {
+        SceneStack::new(Box::new(Playing))
+    }
+
+    //Keys that work the same on every scene and while watching a replay
+    pub fn handle_global(&mut self, key: Key, game: &mut Game) -> bool {
+        match key {
+            Key::Char('c') => game.next_theme(),
+            Key::Char('a') => game.switch_animation_speed(),
+            _ => return false,
+        }
+        true
+    }
+
+    pub fn handle(&mut self, key: Key, game: &mut Game) -> Option<Command> {
+        if self.handle_global(key, game) {
+            return None;
+        }
+        let transition = match self.scenes.last_mut() {
+            Some(scene) => scene.handle(key, game),
+            None => return None,
+        };
+        self.apply(transition)
+    }
+
+    pub fn update(&mut self, game: &Game) {
+        //A few rounds at most, e.g. a restart pops the game over scene and nothing else follows
+        for _ in 0..4 {
+            let transition = match self.scenes.last_mut() {
+                Some(scene) => scene.update(game),
+                None => return,
+            };
+            if let Transition::None = transition {
+                return;
+            }
+            self.apply(transition);
+        }
+    }
+
+    fn apply(&mut self, transition: Transition) -> Option<Command> {
+        match transition {
+            Transition::None => {}
+            Transition::Push(scene) => self.scenes.push(scene),
+            Transition::Pop => {
+                self.scenes.pop();
+            }
+            Transition::Reset(scene) => self.scenes = vec![scene],
+            Transition::Command(command) => {
+                if let Command::NewGame(_) = command {
+                    self.scenes = vec![Box::new(Playing)];
+                }
+                return Some(command);
+            }
+        }
+        None
+    }
+
+    pub fn draw(&self, game: &Game, renderer: &mut dyn Renderer, screen: Vector) {
+        let bottom = self
+            .scenes
+            .iter()
+            .rposition(|scene| !scene.is_overlay())
+            .unwrap_or(0);
+        for scene in self.scenes[bottom..].iter() {
+            scene.draw(game, renderer, screen);
+        }
+    }
+}
+
+//A list of choices moved through with the arrows and picked with Enter
+struct Menu {
+    items: Vec<&'static str>,
+    selected: usize,
+}
+
+impl Menu {
+    fn new(items: Vec<&'static str>) -> Menu {
+        Menu { items, selected: 0 }
+    }
+
+    //The chosen item once Enter is pressed
+    fn handle(&mut self, key: Key) -> Option<&'static str> {
+        match key {
+            Key::Up => self.selected = (self.selected + self.items.len() - 1) % self.items.len(),
+            Key::Down => self.selected = (self.selected + 1) % self.items.len(),
+            Key::Enter => return Some(self.items[self.selected]),
+            _ => {}
+        }
+        None
+    }
+
+    fn draw(&self, renderer: &mut dyn Renderer, pos: Vector, game: &Game) {
+        let theme = game.themes.current();
+        for (i, item) in self.items.iter().enumerate() {
+            let (marker, color) = if i == self.selected {
+                ("> ", theme.heading)
+            } else {
+                ("  ", theme.text)
+            };
+            renderer.put_text(
+                pos + Vector::new(0, i as i32),
+                &format!("{}{}", marker, item),
+                color,
+            );
+        }
+    }
+}
+
+pub struct Title {
+    menu: Menu,
+}
+
+impl Title {
+    pub fn new() -> Title {
+        Title {
+            menu: Menu::new(vec!["New game", "High scores", "Help", "Quit"]),
+        }
+    }
+}
+
+impl Default for Title {
+    fn default() -> Title {
+        Title::new()
+    }
+}
+
+impl Scene for Title {
+    fn handle(&mut self, key: Key, game: &mut Game) -> Transition {
+        match self.menu.handle(key) {
+            Some("New game") => Transition::Push(Box::new(NewGame { seed: game.seed })),
+            Some("High scores") => Transition::Push(Box::new(HighScores)),
+            Some("Help") => Transition::Push(Box::new(Help)),
+            Some("Quit") => Transition::Command(Command::Quit),
+            _ if key == Key::Escape => Transition::Command(Command::Quit),
+            _ => Transition::None,
+        }
+    }
+
+    fn draw(&self, game: &Game, renderer: &mut dyn Renderer, _screen: Vector) {
+        let theme = game.themes.current();
+        renderer.put_text(Vector::new(3, 3), "7DRL 2019", theme.heading);
+        renderer.put_text(
+            Vector::new(3, 5),
+            "Sneak through the dark with a pocket full of pebbles.",
+            theme.text,
+        );
+        self.menu.draw(renderer, Vector::new(3, 8), game);
+    }
+}
+
+//Settings picked before the run starts, colours and animations also change at any time
+pub struct NewGame {
+    seed: u64,
+}
+
+impl Scene for NewGame {
+    fn handle(&mut self, key: Key, _game: &mut Game) -> Transition {
+        match key {
+            Key::Char('r') => self.seed = thread_rng().gen(),
+            Key::Enter => return Transition::Command(Command::NewGame(self.seed)),
+            Key::Escape => return Transition::Pop,
+            _ => {}
+        }
+        Transition::None
+    }
+
+    fn draw(&self, game: &Game, renderer: &mut dyn Renderer, _screen: Vector) {
+        let theme = game.themes.current();
+        renderer.put_text(Vector::new(3, 3), "New game", theme.heading);
+        let lines = [
+            format!("Seed: {}  (R rolls a new one)", self.seed),
+            format!("Colours: {}  (C changes)", game.themes.current().name),
+            format!("Animations: {}  (A changes)", game.animations.speed.name()),
+        ];
+        for (i, line) in lines.iter().enumerate() {
+            renderer.put_text(Vector::new(3, 5 + i as i32), line, theme.text);
+        }
+        renderer.put_text(
+            Vector::new(3, 9),
+            "Enter starts, Escape goes back.",
+            theme.border,
+        );
+    }
+}
+
+pub struct Playing;
+
+impl Scene for Playing {
+    fn handle(&mut self, key: Key, game: &mut Game) -> Transition {
+        let action = match key {
+            Key::Right => Action::Move(1, 0),
+            Key::Left => Action::Move(-1, 0),
+            Key::Up => Action::Move(0, -1),
+            Key::Down => Action::Move(0, 1),
+            Key::Char('.') => Action::Wait,
+            Key::Char('f') => Action::Search,
+            Key::Char('d') => Action::Stay,
+            Key::Char('s') => Action::Sneak,
+            Key::Char('t') => Action::Throw,
+            Key::Char('i') => return Transition::Push(Box::new(Inventory)),
+            Key::Char('x') => {
+                game.start_looking();
+                return Transition::Push(Box::new(Look));
+            }
+            Key::Char('?') => return Transition::Push(Box::new(Help)),
+            Key::Escape => return Transition::Push(Box::new(Pause::new())),
+            _ => return Transition::None,
+        };
+        Transition::Command(Command::Act(action))
+    }
+
+    fn update(&mut self, game: &Game) -> Transition {
+        if game.is_dead() {
+            Transition::Push(Box::new(GameOver))
+        } else if game.has_escaped() {
+            Transition::Push(Box::new(Victory))
+        } else if game.is_choosing_perk() {
+            Transition::Push(Box::new(LevelUp::new(game)))
+        } else if game.is_aiming() {
+            Transition::Push(Box::new(Throwing))
+        } else {
+            Transition::None
+        }
+    }
+
+    fn draw(&self, game: &Game, renderer: &mut dyn Renderer, screen: Vector) {
+        game.draw(renderer, screen);
+    }
+}
+
+//Lasts as long as the crosshair does
+pub struct Throwing;
+
+impl Scene for Throwing {
+    fn handle(&mut self, key: Key, _game: &mut Game) -> Transition {
+        let action = match key {
+            Key::Right => Action::Move(1, 0),
+            Key::Left => Action::Move(-1, 0),
+            Key::Up => Action::Move(0, -1),
+            Key::Down => Action::Move(0, 1),
+            Key::Char('t') | Key::Enter => Action::Fire,
+            Key::Escape => Action::Cancel,
+            _ => return Transition::None,
+        };
+        Transition::Command(Command::Act(action))
+    }
+
+    fn update(&mut self, game: &Game) -> Transition {
+        if game.is_aiming() {
+            Transition::None
+        } else {
+            Transition::Pop
+        }
+    }
+
+    fn draw(&self, game: &Game, renderer: &mut dyn Renderer, screen: Vector) {
+        game.draw(renderer, screen);
+    }
+}
+
+//Lasts until every level-up has its perk, there's no way to skip it
+pub struct LevelUp {
+    menu: Menu,
+}
+
+impl LevelUp {
+    pub fn new(game: &Game) -> LevelUp {
+        LevelUp {
+            menu: Menu::new(LevelUp::choices(game)),
+        }
+    }
+
+    //Perks taken once are left out, so this changes after every pick
+    fn choices(game: &Game) -> Vec<&'static str> {
+        PERKS
+            .iter()
+            .filter(|perk| perk.is_repeatable() || game.ui_data.ranks(**perk) == 0)
+            .map(|perk| perk.title())
+            .collect()
+    }
+}
+
+impl Scene for LevelUp {
+    fn handle(&mut self, key: Key, _game: &mut Game) -> Transition {
+        match self.menu.handle(key).and_then(Perk::from_title) {
+            Some(perk) => Transition::Command(Command::Act(Action::Perk(perk))),
+            None => Transition::None,
+        }
+    }
+
+    fn update(&mut self, game: &Game) -> Transition {
+        if !game.is_choosing_perk() {
+            return Transition::Pop;
+        }
+        //Another level-up is still waiting, without the perk that was just taken
+        let choices = LevelUp::choices(game);
+        if choices != self.menu.items {
+            self.menu = Menu::new(choices);
+        }
+        Transition::None
+    }
+
+    fn draw(&self, game: &Game, renderer: &mut dyn Renderer, screen: Vector) {
+        game.draw(renderer, screen);
+
+        let theme = game.themes.current();
+        let area_pos = Vector::new(2, 2);
+        let area_size = Vector::new(44, 10);
+        ui::draw_box(renderer, area_pos, area_size, theme);
+        renderer.put_text(
+            area_pos + Vector::new(2, 1),
+            &format!("Level {}! Pick a perk:", game.ui_data.level),
+            theme.heading,
+        );
+        self.menu.draw(renderer, area_pos + Vector::new(2, 3), game);
+        renderer.put_text(
+            area_pos + Vector::new(2, 8),
+            "Arrows choose, Enter picks.",
+            theme.border,
+        );
+    }
+}
+
+//Moves the crosshair freely and describes what's under it
+pub struct Look;
+
+impl Scene for Look {
+    fn handle(&mut self, key: Key, game: &mut Game) -> Transition {
+        let direction = match key {
+            Key::Right => Vector::new(1, 0),
+            Key::Left => Vector::new(-1, 0),
+            Key::Up => Vector::new(0, -1),
+            Key::Down => Vector::new(0, 1),
+            Key::Escape | Key::Char('x') => {
+                game.stop_looking();
+                return Transition::Pop;
+            }
+            _ => return Transition::None,
+        };
+        game.move_cursor(direction);
+        Transition::None
+    }
+
+    fn draw(&self, game: &Game, renderer: &mut dyn Renderer, screen: Vector) {
+        game.draw(renderer, screen);
+
+        let theme = game.themes.current();
+        let lines = game.describe_cursor();
+        let width = lines.iter().map(|line| line.len()).max().unwrap_or(0) as i32 + 4;
+        let width = width.min(game.camera.size.x as i32);
+        let height = lines.len() as i32 + 2;
+        //At the top of the map window, or at the bottom when the cursor is up there
+        let cursor = game
+            .cursor_pos()
+            .and_then(|pos| game.camera.to_screen(pos))
+            .unwrap_or_default();
+        let top = if cursor.y < height as f32 + 1.0 {
+            game.camera.size.y as i32 - height
+        } else {
+            0
+        };
+        ui::draw_box(
+            renderer,
+            Vector::new(0, top),
+            Vector::new(width, height),
+            theme,
+        );
+        for (i, line) in lines.iter().enumerate() {
+            renderer.put_text(Vector::new(2, top + 1 + i as i32), line, theme.text);
+        }
+    }
+}
+
+//Lasts until the player restarts
+pub struct GameOver;
+
+impl Scene for GameOver {
+    fn handle(&mut self, key: Key, _game: &mut Game) -> Transition {
+        match key {
+            Key::Char('r') => Transition::Command(Command::Act(Action::Restart)),
+            Key::Char('h') => Transition::Push(Box::new(HighScores)),
+            Key::Char('q') => Transition::Command(Command::Quit),
+            Key::Escape => Transition::Reset(Box::new(Title::new())),
+            _ => Transition::None,
+        }
+    }
+
+    fn update(&mut self, game: &Game) -> Transition {
+        if game.is_dead() {
+            Transition::None
+        } else {
+            Transition::Pop
+        }
+    }
+
+    fn draw(&self, game: &Game, renderer: &mut dyn Renderer, screen: Vector) {
+        game.draw(renderer, screen);
+    }
+}
+
+//Same keys as the game over, drawn over the last look at the cave
+pub struct Victory;
+
+impl Scene for Victory {
+    fn handle(&mut self, key: Key, _game: &mut Game) -> Transition {
+        match key {
+            Key::Char('r') => Transition::Command(Command::Act(Action::Restart)),
+            Key::Char('h') => Transition::Push(Box::new(HighScores)),
+            Key::Char('q') => Transition::Command(Command::Quit),
+            Key::Escape => Transition::Reset(Box::new(Title::new())),
+            _ => Transition::None,
+        }
+    }
+
+    fn update(&mut self, game: &Game) -> Transition {
+        if game.has_escaped() {
+            Transition::None
+        } else {
+            Transition::Pop
+        }
+    }
+
+    fn draw(&self, game: &Game, renderer: &mut dyn Renderer, screen: Vector) {
+        game.draw(renderer, screen);
+
+        let theme = game.themes.current();
+        let data = &game.ui_data;
+        let area_pos = Vector::new(2, 2);
+        let area_size = Vector::new(44, 10);
+        ui::draw_box(renderer, area_pos, area_size, theme);
+        renderer.put_text(
+            area_pos + Vector::new(2, 1),
+            "You escaped the cave!",
+            theme.heading,
+        );
+        let lines = [
+            format!("Score: {}", score::score(data, true)),
+            format!("Deepest level: {}", data.deepest),
+            format!("Turns: {}  Kills: {}", data.turn, data.kills),
+        ];
+        for (i, line) in lines.iter().enumerate() {
+            renderer.put_text(area_pos + Vector::new(2, 3 + i as i32), line, theme.text);
+        }
+        renderer.put_text(
+            area_pos + Vector::new(2, 7),
+            "R starts over, H shows high scores,",
+            theme.border,
+        );
+        renderer.put_text(
+            area_pos + Vector::new(2, 8),
+            "Q quits, Escape goes to the title.",
+            theme.border,
+        );
+    }
+}
+
+pub struct Pause {
+    menu: Menu,
+}
+
+impl Pause {
+    pub fn new() -> Pause {
+        Pause {
+            menu: Menu::new(vec![
+                "Resume",
+                "Help",
+                "High scores",
+                "Quit to title",
+                "Quit game",
+            ]),
+        }
+    }
+}
+
+impl Default for Pause {
+    fn default() -> Pause {
+        Pause::new()
+    }
+}
+
+impl Scene for Pause {
+    fn handle(&mut self, key: Key, _game: &mut Game) -> Transition {
+        match self.menu.handle(key) {
+            Some("Resume") => Transition::Pop,
+            Some("Help") => Transition::Push(Box::new(Help)),
+            Some("High scores") => Transition::Push(Box::new(HighScores)),
+            Some("Quit to title") => Transition::Reset(Box::new(Title::new())),
+            Some("Quit game") => Transition::Command(Command::Quit),
+            _ if key == Key::Escape => Transition::Pop,
+            _ => Transition::None,
+        }
+    }
+
+    fn draw(&self, game: &Game, renderer: &mut dyn Renderer, _screen: Vector) {
+        let area_pos = Vector::new(2, 2);
+        let area_size = Vector::new(20, 10);
+        ui::draw_box(renderer, area_pos, area_size, game.themes.current());
+        renderer.put_text(
+            area_pos + Vector::new(2, 1),
+            "Paused",
+            game.themes.current().heading,
+        );
+        self.menu.draw(renderer, area_pos + Vector::new(2, 3), game);
+    }
+
+    fn is_overlay(&self) -> bool {
+        true
+    }
+}
+
+pub struct Inventory;
+
+impl Scene for Inventory {
+    fn handle(&mut self, key: Key, _game: &mut Game) -> Transition {
+        match key {
+            Key::Escape | Key::Char('i') => Transition::Pop,
+            _ => Transition::None,
+        }
+    }
+
+    fn draw(&self, game: &Game, renderer: &mut dyn Renderer, _screen: Vector) {
+        let theme = game.themes.current();
+        renderer.put_text(Vector::new(3, 3), "Inventory", theme.heading);
+        let pebbles = match game.ui_data.pebbles {
+            0 => String::from("No pebbles"),
+            1 => String::from("1 pebble"),
+            n => format!("{} pebbles", n),
+        };
+        renderer.put_text(Vector::new(3, 5), &pebbles, theme.text);
+        renderer.put_text(Vector::new(3, 6), game.dog_status(), theme.text);
+
+        renderer.put_text(Vector::new(3, 7), "Perks", theme.heading);
+        let mut row = 9;
+        for perk in PERKS.iter() {
+            let ranks = game.ui_data.ranks(*perk);
+            if ranks > 0 {
+                renderer.put_text(
+                    Vector::new(3, row),
+                    &format!("{} x{}", perk.title(), ranks),
+                    theme.text,
+                );
+                row += 1;
+            }
+        }
+        if row == 9 {
+            renderer.put_text(Vector::new(3, row), "None yet", theme.text);
+            row += 1;
+        }
+        renderer.put_text(Vector::new(3, row + 1), "Escape goes back.", theme.border);
+    }
+}
+
+pub struct Help;
+
+const HELP: [&str; 22] = [
+    "Arrows      move, or attack what's in the way",
+    ".           wait a turn",
+    "F           feel around you for hidden traps",
+    "S           sneak or stand up",
+    "D           tell your dog to stay, or to follow again",
+    "T           aim a pebble: arrows aim, T or Enter throws, Escape cancels",
+    "I           inventory",
+    "X           look around: arrows move the cursor, X or Escape stops",
+    "?           this help",
+    "C           colours",
+    "A           animation speed",
+    "Escape      pause",
+    "",
+    "Find the artifact (&) at the bottom and bring it up to the exit (<).",
+    "Light and noise make you easier to notice, stairs (>) lead deeper.",
+    "Your torch burns down every turn, flasks of oil (!) refill it.",
+    "Exploring, picking things up and kills earn XP towards perks.",
+    "Water (~ =) and rubble (%) are slow and loud, chasms (;) drop you a level.",
+    "You can see past stalagmites (I) and crystal (*), walk through fungus (\").",
+    "Gates (+) open with a bumped lever (X) or a plate (_) stepped or thrown on.",
+    "Pebbles thrown ahead set off hidden traps (^) before you step on them.",
+    "Your dog (d) fetches thrown pebbles and barks at creatures it hears.",
+];
+
+impl Scene for Help {
+    fn handle(&mut self, key: Key, _game: &mut Game) -> Transition {
+        match key {
+            Key::Escape | Key::Char('?') => Transition::Pop,
+            _ => Transition::None,
+        }
+    }
+
+    fn draw(&self, game: &Game, renderer: &mut dyn Renderer, _screen: Vector) {
+        let theme = game.themes.current();
+        renderer.put_text(Vector::new(3, 3), "Help", theme.heading);
+        for (i, line) in HELP.iter().enumerate() {
+            renderer.put_text(Vector::new(3, 5 + i as i32), line, theme.text);
+        }
+        renderer.put_text(
+            Vector::new(3, 6 + HELP.len() as i32),
+            "Escape goes back.",
+            theme.border,
+        );
+    }
+}
+
+pub struct HighScores;
+
+impl Scene for HighScores {
+    fn handle(&mut self, key: Key, _game: &mut Game) -> Transition {
+        match key {
+            Key::Escape => Transition::Pop,
+            _ => Transition::None,
+        }
+    }
+
+    fn draw(&self, game: &Game, renderer: &mut dyn Renderer, _screen: Vector) {
+        ui::draw_high_scores(renderer, &game.high_scores, game.themes.current());
+    }
+}
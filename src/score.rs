@@ -0,0 +1,161 @@
+use crate::ui::UIData;
+use quicksilver::saving;
+use serde::{Deserialize, Serialize};
+
+const APP_NAME: &str = "rl_2019";
+const PROFILE: &str = "high_scores";
+//Oldest runs are forgotten past this many
+const HISTORY_SIZE: usize = 100;
+//Best runs kept apart from the history, so old records outlive it
+const TOP_SIZE: usize = 10;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RunRecord {
+    pub seed: u64,
+    pub score: u32,
+    pub depth: u32,
+    pub turns: u32,
+    pub kills: u32,
+    pub pebbles_collected: u32,
+    pub pebbles_thrown: u32,
+    pub won: bool,
+    pub cause: String,
+}
+
+impl RunRecord {
+    pub fn new(seed: u64, data: &UIData, won: bool, cause: &str) -> RunRecord {
+        RunRecord {
+            seed,
+            score: score(data, won),
+            depth: data.deepest,
+            turns: data.turn,
+            kills: data.kills,
+            pebbles_collected: data.pebbles_collected,
+            pebbles_thrown: data.pebbles_thrown,
+            won,
+            cause: cause.to_string(),
+        }
+    }
+}
+
+//A pebble only scores for being thrown as many times as fresh ones were collected,
+//so throwing the same one over and over is worth nothing
+pub fn score(data: &UIData, won: bool) -> u32 {
+    let win_bonus = if won { 1000 } else { 0 };
+    let thrown = data.pebbles_thrown.min(data.pebbles_collected);
+    data.deepest * 100
+        + data.kills * 25
+        + data.pebbles_collected * 2
+        + thrown
+        + data.turn / 10
+        + win_bonus
+}
+
+//The latest finished runs, newest last, and the best ones ever; only saved to disk when loaded from it
+#[derive(Default, Serialize, Deserialize)]
+pub struct HighScores {
+    pub runs: Vec<RunRecord>,
+    //Best first, missing from files saved before it was kept
+    #[serde(default)]
+    top: Vec<RunRecord>,
+    #[serde(skip)]
+    persistent: bool,
+}
+
+impl HighScores {
+    pub fn load() -> HighScores {
+        let mut scores = saving::load::<HighScores>(APP_NAME, PROFILE).unwrap_or_default();
+        if scores.top.is_empty() {
+            for run in scores.runs.clone() {
+                scores.keep_if_best(run);
+            }
+        }
+        scores.persistent = true;
+        scores
+    }
+
+    pub fn record(&mut self, run: RunRecord) {
+        self.keep_if_best(run.clone());
+        self.runs.push(run);
+        if self.runs.len() > HISTORY_SIZE {
+            self.runs.remove(0);
+        }
+        if self.persistent {
+            if let Err(err) = saving::save(APP_NAME, PROFILE, self) {
+                eprintln!("Could not save the high scores: {}", err);
+            }
+        }
+    }
+
+    //Ties go to the earlier run
+    fn keep_if_best(&mut self, run: RunRecord) {
+        let rank = self
+            .top
+            .iter()
+            .position(|best| best.score < run.score)
+            .unwrap_or(self.top.len());
+        self.top.insert(rank, run);
+        self.top.truncate(TOP_SIZE);
+    }
+
+    pub fn best(&self, count: usize) -> Vec<&RunRecord> {
+        self.top.iter().take(count).collect()
+    }
+
+    pub fn recent(&self, count: usize) -> impl Iterator<Item = &RunRecord> {
+        self.runs.iter().rev().take(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(score: u32) -> RunRecord {
+        RunRecord {
+            seed: 0,
+            score,
+            depth: 1,
+            turns: 0,
+            kills: 0,
+            pebbles_collected: 0,
+            pebbles_thrown: 0,
+            won: false,
+            cause: String::from("the dark"),
+        }
+    }
+
+    #[test]
+    fn best_runs_outlive_the_history() {
+        let mut scores = HighScores::default();
+        scores.record(run(500));
+        for _ in 0..HISTORY_SIZE * 2 {
+            scores.record(run(10));
+        }
+        assert_eq!(scores.runs.len(), HISTORY_SIZE);
+        assert_eq!(scores.best(1)[0].score, 500);
+        assert_eq!(scores.best(100).len(), TOP_SIZE);
+    }
+
+    #[test]
+    fn best_runs_come_first() {
+        let mut scores = HighScores::default();
+        for score in [30, 50, 10, 40] {
+            scores.record(run(score));
+        }
+        let best: Vec<u32> = scores.best(3).iter().map(|run| run.score).collect();
+        assert_eq!(best, vec![50, 40, 30]);
+    }
+
+    #[test]
+    fn rethrowing_a_pebble_scores_nothing() {
+        let mut data = UIData::new();
+        data.pebbles_collected = 3;
+        data.pebbles_thrown = 3;
+        let fair = score(&data, false);
+        data.pebbles_thrown = 40;
+        assert_eq!(score(&data, false), fair);
+        data.pebbles_thrown = 0;
+        assert!(score(&data, false) < fair);
+    }
+}
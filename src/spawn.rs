@@ -0,0 +1,149 @@
+//Which creatures and items appear at each depth, how often and in what numbers
+use crate::data;
+use crate::entities::Entity;
+use quicksilver::geom::Vector;
+use rand::{rngs::StdRng, Rng};
+use slotmap::DefaultKey;
+use std::io;
+
+//Compiled in for the same reason as the vaults
+const SPAWNS: &str = include_str!("../static/spawns.txt");
+
+pub struct SpawnEntry {
+    pub id: String,
+    pub weight: u32,
+    pub min_group: u32,
+    pub max_group: u32,
+}
+
+pub struct SpawnTable {
+    pub first_depth: u32,
+    //None for every depth below the first
+    pub last_depth: Option<u32>,
+    pub groups: u32,
+    pub entries: Vec<SpawnEntry>,
+}
+
+impl SpawnTable {
+    pub fn covers(&self, depth: u32) -> bool {
+        depth >= self.first_depth && self.last_depth.map_or(true, |last| depth <= last)
+    }
+
+    //A random entry among those accepted, more likely the heavier it is
+    pub fn pick(
+        &self,
+        rng: &mut StdRng,
+        accepts: impl Fn(&SpawnEntry) -> bool,
+    ) -> Option<&SpawnEntry> {
+        let entries: Vec<&SpawnEntry> =
+            self.entries.iter().filter(|entry| accepts(entry)).collect();
+        let total_weight: u32 = entries.iter().map(|entry| entry.weight).sum();
+        if total_weight == 0 {
+            return None;
+        }
+        let mut roll = rng.gen_range(0, total_weight);
+        entries.into_iter().find(|entry| {
+            if roll < entry.weight {
+                true
+            } else {
+                roll -= entry.weight;
+                false
+            }
+        })
+    }
+}
+
+//"depth <first> [last]" starts a table, then "groups <n>" and "<id> <weight> <min> <max>" lines
+pub fn parse(text: &str) -> io::Result<Vec<SpawnTable>> {
+    let invalid = |line: &str| data::invalid_line("spawn", line);
+
+    let mut tables: Vec<SpawnTable> = vec![];
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with("//") {
+            continue;
+        }
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let numbers: Vec<u32> = words[1..]
+            .iter()
+            .map(|word| word.parse::<u32>())
+            .collect::<Result<_, _>>()
+            .map_err(|_| invalid(line))?;
+        match (words[0], numbers.as_slice()) {
+            ("depth", [first]) | ("depth", [first, _]) => {
+                let last = numbers.get(1).copied();
+                if *first == 0 || last.is_some_and(|last| last < *first) {
+                    return Err(invalid(line));
+                }
+                tables.push(SpawnTable {
+                    first_depth: *first,
+                    last_depth: last,
+                    groups: 0,
+                    entries: vec![],
+                });
+            }
+            ("groups", [groups]) => {
+                let table = tables.last_mut().ok_or_else(|| invalid(line))?;
+                table.groups = *groups;
+            }
+            (id, [weight, min_group, max_group]) => {
+                let table = tables.last_mut().ok_or_else(|| invalid(line))?;
+                if Entity::spawn(id, DefaultKey::default(), Vector::ZERO).is_none()
+                    || *weight == 0
+                    || *min_group == 0
+                    || min_group > max_group
+                {
+                    return Err(invalid(line));
+                }
+                table.entries.push(SpawnEntry {
+                    id: id.to_string(),
+                    weight: *weight,
+                    min_group: *min_group,
+                    max_group: *max_group,
+                });
+            }
+            _ => return Err(invalid(line)),
+        }
+    }
+    if tables.iter().any(|table| table.entries.is_empty()) {
+        return Err(data::invalid("A spawn table has no entries"));
+    }
+    Ok(tables)
+}
+
+pub fn bundled() -> Vec<SpawnTable> {
+    parse(SPAWNS).expect("The bundled spawn tables could not be parsed.")
+}
+
+//The first table covering the depth, or the deepest one past the end of the list
+pub fn table_for(tables: &[SpawnTable], depth: u32) -> Option<&SpawnTable> {
+    tables
+        .iter()
+        .find(|table| table.covers(depth))
+        .or_else(|| tables.iter().max_by_key(|table| table.first_depth))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bundled_tables_parse() {
+        let tables = bundled();
+        assert!(table_for(&tables, 1).is_some());
+        assert!(table_for(&tables, 100).is_some());
+    }
+
+    #[test]
+    fn typo_in_an_id_fails() {
+        assert!(parse("depth 1\ngroups 5\npebble 10 1 2").is_ok());
+        assert!(parse("depth 1\ngroups 5\npebbel 10 1 2").is_err());
+    }
+
+    #[test]
+    fn malformed_weight_fails() {
+        assert!(parse("depth 1\ngroups 5\npebble x 1 2").is_err());
+        assert!(parse("depth 1\ngroups 5\npebble 0 1 2").is_err());
+        assert!(parse("depth 1\ngroups 5\npebble -3 1 2").is_err());
+    }
+}
@@ -0,0 +1,189 @@
+//Conditions on the player or a creature, each lasting a number of turns
+use crate::entities::Entity;
+use crate::ui::MessageLog;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Status {
+    //Can't see past its own tile
+    Blinded,
+    //Stumbles in a random direction instead of where it meant to go
+    Stunned,
+    //Doesn't hear noises
+    Deafened,
+    //Loses hp every turn
+    Poisoned,
+    //Acts twice for every turn of everyone else
+    Hasted,
+}
+
+pub const ALL: [Status; 5] = [
+    Status::Blinded,
+    Status::Stunned,
+    Status::Deafened,
+    Status::Poisoned,
+    Status::Hasted,
+];
+
+//Chance in percent that a crawler bite poisons, and for how long
+pub const BITE_POISON_PERC: u32 = 25;
+pub const BITE_POISON_TURNS: u32 = 3;
+//Chance in percent that brushing through fungus blinds with its spores, and for how long
+pub const SPORE_BLIND_PERC: u32 = 20;
+pub const SPORE_BLIND_TURNS: u32 = 2;
+//Fright speeds up a player left on their last hit point
+pub const FRIGHT_HASTE_TURNS: u32 = 4;
+
+impl Status {
+    pub fn name(self) -> &'static str {
+        match self {
+            Status::Blinded => "blinded",
+            Status::Stunned => "stunned",
+            Status::Deafened => "deafened",
+            Status::Poisoned => "poisoned",
+            Status::Hasted => "hasted",
+        }
+    }
+
+    //Shown in the side panel next to the turns left
+    pub fn abbreviation(self) -> &'static str {
+        match self {
+            Status::Blinded => "Bln",
+            Status::Stunned => "Stn",
+            Status::Deafened => "Dfn",
+            Status::Poisoned => "Psn",
+            Status::Hasted => "Hst",
+        }
+    }
+
+    fn starts(self) -> &'static str {
+        match self {
+            Status::Blinded => "You are blinded!",
+            Status::Stunned => "You are stunned!",
+            Status::Deafened => "You are deafened!",
+            Status::Poisoned => "You are poisoned!",
+            Status::Hasted => "You speed up!",
+        }
+    }
+
+    fn wears_off(self) -> &'static str {
+        match self {
+            Status::Blinded => "You can see again.",
+            Status::Stunned => "Your head clears.",
+            Status::Deafened => "You can hear again.",
+            Status::Poisoned => "The poison wears off.",
+            Status::Hasted => "You slow down.",
+        }
+    }
+
+    //More poison adds up, anything else only lasts as long as the longest dose
+    fn stacks(self) -> bool {
+        self == Status::Poisoned
+    }
+
+    fn index(self) -> usize {
+        ALL.iter().position(|status| *status == self).unwrap()
+    }
+}
+
+//Turns left for each status, 0 when it's not active
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Statuses {
+    turns: [u32; ALL.len()],
+}
+
+impl Statuses {
+    pub fn new() -> Statuses {
+        Statuses::default()
+    }
+
+    //Returns true if the status wasn't active yet
+    pub fn add(&mut self, status: Status, turns: u32) -> bool {
+        let left = &mut self.turns[status.index()];
+        let started = *left == 0;
+        *left = if status.stacks() {
+            *left + turns
+        } else {
+            (*left).max(turns)
+        };
+        started
+    }
+
+    pub fn has(&self, status: Status) -> bool {
+        self.turns_left(status) > 0
+    }
+
+    pub fn turns_left(&self, status: Status) -> u32 {
+        self.turns[status.index()]
+    }
+
+    pub fn active(&self) -> Vec<Status> {
+        ALL.iter()
+            .copied()
+            .filter(|status| self.has(*status))
+            .collect()
+    }
+
+    //One turn passes, returns the statuses that just ended
+    pub fn tick(&mut self) -> Vec<Status> {
+        let mut ended = vec![];
+        for status in ALL.iter() {
+            let left = &mut self.turns[status.index()];
+            if *left > 0 {
+                *left -= 1;
+                if *left == 0 {
+                    ended.push(*status);
+                }
+            }
+        }
+        ended
+    }
+}
+
+//Only the player is ever afflicted, so the messages speak to them
+pub fn afflict(entity: &mut Entity, status: Status, turns: u32, message_log: &mut MessageLog) {
+    if entity.statuses.add(status, turns) {
+        message_log.push(status.starts());
+    }
+}
+
+pub fn tick(entity: &mut Entity, message_log: &mut MessageLog) {
+    for status in entity.statuses.tick() {
+        message_log.push(status.wears_off());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn poison_adds_up() {
+        let mut statuses = Statuses::new();
+        assert!(statuses.add(Status::Poisoned, 3));
+        assert!(!statuses.add(Status::Poisoned, 2));
+        assert_eq!(statuses.turns_left(Status::Poisoned), 5);
+    }
+
+    #[test]
+    fn other_statuses_keep_the_longest_dose() {
+        for status in ALL.iter().filter(|status| **status != Status::Poisoned) {
+            let mut statuses = Statuses::new();
+            assert!(statuses.add(*status, 3));
+            assert!(!statuses.add(*status, 1));
+            assert_eq!(statuses.turns_left(*status), 3);
+            statuses.add(*status, 5);
+            assert_eq!(statuses.turns_left(*status), 5);
+        }
+    }
+
+    #[test]
+    fn tick_reports_what_ended() {
+        let mut statuses = Statuses::new();
+        statuses.add(Status::Stunned, 1);
+        statuses.add(Status::Deafened, 2);
+        assert_eq!(statuses.tick(), vec![Status::Stunned]);
+        assert_eq!(statuses.active(), vec![Status::Deafened]);
+        assert_eq!(statuses.tick(), vec![Status::Deafened]);
+        assert!(statuses.active().is_empty());
+    }
+}
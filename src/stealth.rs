@@ -0,0 +1,26 @@
+//How easy the player is to notice: light on their tile, noise of their last action and posture
+
+//Noise made by each kind of action
+pub const MOVE_NOISE: i32 = 3;
+pub const SNEAK_NOISE: i32 = 1;
+pub const FIGHT_NOISE: i32 = 4;
+pub const THROW_NOISE: i32 = 1;
+pub const PICKUP_NOISE: i32 = 1;
+pub const LEVER_NOISE: i32 = 4;
+//Loudness of a pebble hitting the ground, creatures within it come to look
+pub const PEBBLE_NOISE: i32 = 8;
+
+//Each level of light on the player's tile counts this much
+const LIGHT_WEIGHT: i32 = 3;
+//Standing upright instead of sneaking
+const STANDING: i32 = 2;
+
+pub fn visibility(light: i32, noise: i32, sneaking: bool) -> i32 {
+    let posture = if sneaking { 0 } else { STANDING };
+    light * LIGHT_WEIGHT + noise + posture
+}
+
+//Visibility fades with distance, and has to beat the creature's perception to be noticed
+pub fn detects(visibility: i32, distance: i32, perception: i32) -> bool {
+    visibility - distance > perception
+}
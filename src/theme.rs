@@ -0,0 +1,202 @@
+use crate::data;
+use crate::map::Palette;
+use quicksilver::graphics::Color;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+//Colours for every palette role, tiles and entities fall back to their own colours
+pub struct Theme {
+    pub name: String,
+    pub background: Color,
+    pub border: Color,
+    pub text: Color,
+    pub heading: Color,
+    //By tile or entity name, while in view and while only remembered
+    lit: HashMap<String, Color>,
+    unlit: HashMap<String, Color>,
+}
+
+impl Theme {
+    pub fn new() -> Theme {
+        Theme {
+            name: String::from("Default"),
+            background: Palette::DARK_BLUE,
+            border: Palette::LIGHT_BLUE,
+            text: Palette::WHITE,
+            heading: Palette::YELLOW,
+            lit: HashMap::new(),
+            unlit: HashMap::new(),
+        }
+    }
+
+    //"name <text>", "<role> #rrggbb", "lit <name> #rrggbb" and "unlit <name> #rrggbb" lines, // for comments
+    pub fn parse(text: &str) -> io::Result<Theme> {
+        let invalid = |line: &str| data::invalid_line("theme", line);
+
+        let mut theme = Theme::new();
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with("//") {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix("name ") {
+                theme.name = name.trim().to_string();
+                continue;
+            }
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let color = words
+                .last()
+                .and_then(|hex| parse_color(hex))
+                .ok_or_else(|| invalid(line))?;
+            match words[..] {
+                ["background", _] => theme.background = color,
+                ["border", _] => theme.border = color,
+                ["text", _] => theme.text = color,
+                ["heading", _] => theme.heading = color,
+                ["lit", name, _] => {
+                    theme.lit.insert(name.to_string(), color);
+                }
+                ["unlit", name, _] => {
+                    theme.unlit.insert(name.to_string(), color);
+                }
+                _ => return Err(invalid(line)),
+            }
+        }
+        Ok(theme)
+    }
+
+    pub fn lit(&self, name: &str, default: Color) -> Color {
+        self.lit.get(name).copied().unwrap_or(default)
+    }
+
+    pub fn unlit(&self, name: &str, default: Color) -> Color {
+        self.unlit.get(name).copied().unwrap_or(default)
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Theme {
+        Theme::new()
+    }
+}
+
+fn parse_color(hex: &str) -> Option<Color> {
+    let hex = hex.strip_prefix('#')?;
+    if hex.len() != 6 {
+        return None;
+    }
+    let channel = |i: usize| {
+        u8::from_str_radix(hex.get(i..i + 2)?, 16)
+            .ok()
+            .map(|value| value as f32 / 255.0)
+    };
+    Some(Color {
+        r: channel(0)?,
+        g: channel(2)?,
+        b: channel(4)?,
+        a: 1.0,
+    })
+}
+
+//The built-in theme first, then every theme file found at startup
+pub struct Themes {
+    list: Vec<Theme>,
+    current: usize,
+}
+
+impl Themes {
+    pub fn new() -> Themes {
+        Themes {
+            list: vec![Theme::new()],
+            current: 0,
+        }
+    }
+
+    //Files that fail to parse are reported and skipped, the game still starts
+    pub fn load(dir: &Path) -> Themes {
+        let mut themes = Themes::new();
+        let mut paths: Vec<_> = match fs::read_dir(dir) {
+            Ok(entries) => entries
+                .filter_map(|entry| Some(entry.ok()?.path()))
+                .collect(),
+            Err(_) => return themes,
+        };
+        paths.sort();
+        for path in paths {
+            match fs::read_to_string(&path).and_then(|text| Theme::parse(&text)) {
+                Ok(theme) => themes.list.push(theme),
+                Err(error) => eprintln!("Skipping theme {}: {}", path.display(), error),
+            }
+        }
+        themes
+    }
+
+    pub fn current(&self) -> &Theme {
+        &self.list[self.current]
+    }
+
+    pub fn switch(&mut self) -> &Theme {
+        self.current = (self.current + 1) % self.list.len();
+        self.current()
+    }
+}
+
+impl Default for Themes {
+    fn default() -> Themes {
+        Themes::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hex_colours_parse() {
+        let color = parse_color("#ff0080").unwrap();
+        assert_eq!(
+            (color.r, color.g, color.b, color.a),
+            (1.0, 0.0, 128.0 / 255.0, 1.0)
+        );
+        assert!(parse_color("#FFFFFF").is_some());
+    }
+
+    #[test]
+    fn bad_colours_fail() {
+        for hex in ["ff0080", "#ff008", "#ff00800", "#gg0080", "#", "#ff00é"] {
+            assert!(parse_color(hex).is_none(), "{}", hex);
+        }
+        assert!(Theme::parse("border #12345").is_err());
+        assert!(Theme::parse("lit wall").is_err());
+    }
+
+    #[test]
+    fn unknown_keys_fail() {
+        assert!(Theme::parse("foreground #ffffff").is_err());
+        assert!(Theme::parse("lit #ffffff").is_err());
+        assert!(Theme::parse("bright wall #ffffff").is_err());
+    }
+
+    #[test]
+    fn theme_lines_set_colours() {
+        let theme = Theme::parse("//Comment\nname Test\nborder #ffffff\nlit wall #ff0000").unwrap();
+        assert_eq!(theme.name, "Test");
+        assert_eq!(Some(theme.border), parse_color("#ffffff"));
+        assert_eq!(theme.lit("wall", Palette::BLACK).r, 1.0);
+        assert_eq!(theme.unlit("wall", Palette::BLACK), Palette::BLACK);
+    }
+
+    #[test]
+    fn bundled_themes_parse() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("static")
+            .join("themes");
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            let text = fs::read_to_string(&path).unwrap();
+            assert!(Theme::parse(&text).is_ok(), "{}", path.display());
+        }
+    }
+}
@@ -0,0 +1,52 @@
+//The torch burns one unit of fuel a turn, and how much is left sets how far the player sees
+
+pub const MAX_FUEL: u32 = 300;
+//Fuel in one flask of oil
+pub const FLASK_FUEL: u32 = 100;
+//Below this the torch flickers
+pub const LOW_FUEL: u32 = 50;
+
+//Sight radius with a full torch, an empty one only shows the tiles next to the player
+const MAX_RADIUS: u32 = 4;
+const MIN_RADIUS: u32 = 1;
+
+//A wider wick only helps while there's fuel to burn
+pub fn sight_radius(fuel: u32, bonus: u32) -> i32 {
+    let steps = MAX_RADIUS + bonus - MIN_RADIUS;
+    (MIN_RADIUS + (fuel * steps).div_ceil(MAX_FUEL)) as i32
+}
+
+pub fn refill(fuel: u32) -> u32 {
+    (fuel + FLASK_FUEL).min(MAX_FUEL)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn radius_steps_down_with_the_fuel() {
+        assert_eq!(sight_radius(MAX_FUEL, 0), MAX_RADIUS as i32);
+        assert_eq!(sight_radius(201, 0), 4);
+        assert_eq!(sight_radius(200, 0), 3);
+        assert_eq!(sight_radius(101, 0), 3);
+        assert_eq!(sight_radius(100, 0), 2);
+        assert_eq!(sight_radius(1, 0), 2);
+        assert_eq!(sight_radius(0, 0), MIN_RADIUS as i32);
+        for fuel in 1..=MAX_FUEL {
+            assert!(sight_radius(fuel, 0) >= sight_radius(fuel - 1, 0));
+        }
+    }
+
+    #[test]
+    fn wider_wick_needs_fuel() {
+        assert_eq!(sight_radius(MAX_FUEL, 1), MAX_RADIUS as i32 + 1);
+        assert_eq!(sight_radius(0, 1), MIN_RADIUS as i32);
+    }
+
+    #[test]
+    fn refill_stops_at_a_full_torch() {
+        assert_eq!(refill(0), FLASK_FUEL);
+        assert_eq!(refill(MAX_FUEL - 10), MAX_FUEL);
+    }
+}
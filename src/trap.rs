@@ -0,0 +1,38 @@
+//Hidden in the floor until searched for, stepped on or hit by a pebble
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Trap {
+    //Hurts and stuns whoever falls in, swallows pebbles
+    Pit,
+    //Shrieks loud enough to bring every creature around
+    Alarm,
+    //Drops rocks from the ceiling and leaves rubble behind
+    Rockfall,
+}
+
+pub const TRAPS: [Trap; 3] = [Trap::Pit, Trap::Alarm, Trap::Rockfall];
+
+//How loud an alarm is, and a rockfall
+pub const ALARM_NOISE: i32 = 15;
+pub const ROCKFALL_NOISE: i32 = 6;
+//Turns the player stays stunned after a pit or rocks, and deafened next to an alarm
+pub const STUN_TURNS: u32 = 2;
+pub const DEAFEN_TURNS: u32 = 4;
+
+impl Trap {
+    pub fn name(self) -> &'static str {
+        match self {
+            Trap::Pit => "pit",
+            Trap::Alarm => "alarm",
+            Trap::Rockfall => "rockfall",
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            Trap::Pit => "A pit hidden under loose gravel.",
+            Trap::Alarm => "A tripwire strung to a rattling alarm.",
+            Trap::Rockfall => "A loose ceiling, ready to come down.",
+        }
+    }
+}
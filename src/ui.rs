@@ -1,138 +1,241 @@
-use quicksilver::{
-    geom::{Rectangle, Shape, Vector},
-    graphics::{Background::Blended, Background::Img, Image},
-    lifecycle::{Asset, Window},
-};
-
-use crate::map::{Palette, TILE_SIZE};
-use std::collections::HashMap;
+use crate::map;
+use crate::progress::{self, Perk};
+use crate::render::Renderer;
+use crate::score::HighScores;
+use crate::status::Statuses;
+use crate::theme::Theme;
+use crate::torch;
+use quicksilver::geom::Vector;
 
 pub struct UIData {
     pub turn: u32,
     pub pebbles: u32,
-    text: Asset<HashMap<&'static str, Image>>,
+    pub visibility: i32,
+    pub sneaking: bool,
+    pub depth: u32,
+    pub kills: u32,
+    pub pebbles_collected: u32,
+    pub pebbles_thrown: u32,
+    pub fuel: u32,
+    pub level: u32,
+    //Towards the next level
+    pub xp: u32,
+    pub perks: Vec<Perk>,
+    //Level-ups whose perk hasn't been picked yet
+    pub perk_choices: u32,
+    //Deepest level reached so far, the player may have climbed back up since
+    pub deepest: u32,
+    pub has_artifact: bool,
 }
 
 impl UIData {
-    pub fn new(text: Asset<HashMap<&'static str, Image>>) -> UIData {
+    pub fn new() -> UIData {
         UIData {
             turn: 0,
             pebbles: 0,
-            text,
+            visibility: 0,
+            sneaking: false,
+            depth: 1,
+            kills: 0,
+            pebbles_collected: 0,
+            pebbles_thrown: 0,
+            fuel: torch::MAX_FUEL,
+            level: 1,
+            xp: 0,
+            perks: vec![],
+            perk_choices: 0,
+            deepest: 1,
+            has_artifact: false,
         }
     }
+
+    pub fn ranks(&self, perk: Perk) -> u32 {
+        self.perks.iter().filter(|picked| **picked == perk).count() as u32
+    }
+}
+
+impl Default for UIData {
+    fn default() -> UIData {
+        UIData::new()
+    }
 }
 
 pub struct MessageLog {
-    pub log: Vec<&'static str>,
+    pub log: Vec<String>,
     show: usize,
 }
 
 impl MessageLog {
     pub fn new() -> MessageLog {
         MessageLog {
-            log: vec!["dark"],
+            log: vec![String::from("It's dark around you...")],
             show: 15,
         }
     }
-    pub fn push(&mut self, message: &'static str) {
-        self.log.push(message);
+    pub fn push(&mut self, message: &str) {
+        self.log.push(message.to_string());
         if self.log.len() > self.show {
             self.log.remove(0);
         }
     }
 }
 
-const MAP_R_BORDER: i32 = 696;
-const MAP_B_BORDER: i32 = 480;
+impl Default for MessageLog {
+    fn default() -> MessageLog {
+        MessageLog::new()
+    }
+}
 
+//Width of the torch fuel bar, in cells
+const FUEL_BAR: u32 = 20;
+
+//Borders sit right of and below the map window, everything is in cells
 pub fn draw_ui(
-    window: &mut Window,
-    data: &mut UIData,
-    message_log: &mut MessageLog,
-    tileset: &mut HashMap<char, Image>,
+    renderer: &mut dyn Renderer,
+    data: &UIData,
+    statuses: &Statuses,
+    message_log: &MessageLog,
+    viewport: Vector,
+    screen: Vector,
+    theme: &Theme,
 ) {
+    let r_border = viewport.x as i32 + 1;
+    let b_border = viewport.y as i32;
+
     //Borders
-    let v_border = tileset.get(&'|').unwrap();
-    for y in 0..=760 {
-        window.draw(
-            &Rectangle::new(Vector::new(MAP_R_BORDER, y), v_border.area().size()),
-            Blended(&v_border, Palette::LIGHT_BLUE),
+    for y in 0..screen.y as i32 {
+        renderer.put_glyph(Vector::new(r_border, y), '|', theme.border, None);
+    }
+    for x in 0..r_border {
+        renderer.put_glyph(Vector::new(x, b_border), '_', theme.border, None);
+    }
+
+    //Text
+    let pebbles_label = "Pebbles: ";
+    let pebbles_row = b_border + 1;
+    renderer.put_text(Vector::new(1, pebbles_row), pebbles_label, theme.text);
+    for n in 0..data.pebbles as i32 {
+        renderer.put_glyph(
+            Vector::new(1 + pebbles_label.len() as i32 + n, pebbles_row),
+            'o',
+            theme.text,
+            None,
         );
     }
 
-    let h_border = tileset.get(&'_').unwrap();
-    for x in 0..=684 {
-        window.draw(
-            &Rectangle::new(Vector::new(x, MAP_B_BORDER), h_border.area().size()),
-            Blended(&h_border, Palette::LIGHT_BLUE),
+    let mut status = format!(
+        "Level: {}  XP: {}/{}  Depth: {}  Visibility: {}",
+        data.level,
+        data.xp,
+        progress::xp_to_next(data.level),
+        data.depth,
+        data.visibility
+    );
+    if data.sneaking {
+        status.push_str("  Sneaking");
+    }
+    renderer.put_text(Vector::new(1, b_border + 2), &status, theme.text);
+
+    let torch_label = "Torch:   ";
+    let lit = (data.fuel * FUEL_BAR).div_ceil(torch::MAX_FUEL);
+    let bar: String = (0..FUEL_BAR)
+        .map(|n| if n < lit { '=' } else { '-' })
+        .collect();
+    let mut torch = format!("{}{}", torch_label, bar);
+    for status in statuses.active() {
+        torch.push_str(&format!(
+            "  {} {}",
+            status.abbreviation(),
+            statuses.turns_left(status)
+        ));
+    }
+    renderer.put_text(Vector::new(1, b_border + 3), &torch, theme.text);
+
+    let goal = if data.has_artifact {
+        String::from("Goal: take the artifact (&) up to the exit (<)")
+    } else {
+        format!("Goal: find the artifact (&) on depth {}", map::DEEPEST)
+    };
+    renderer.put_text(Vector::new(1, b_border + 4), &goal, theme.text);
+
+    let log_row = b_border + 5;
+    let log_lines = (screen.y as i32 - log_row - 1).max(0) as usize;
+    renderer.put_text(Vector::new(1, log_row), "Messages:", theme.text);
+    message_log
+        .log
+        .iter()
+        .rev()
+        .take(log_lines)
+        .enumerate()
+        .for_each(|(i, message)| {
+            renderer.put_text(Vector::new(1, log_row + 1 + i as i32), message, theme.text);
+        });
+}
+
+//Blank panel with a border, for menus drawn over the game
+pub fn draw_box(renderer: &mut dyn Renderer, pos: Vector, size: Vector, theme: &Theme) {
+    let (right, bottom) = (size.x as i32 - 1, size.y as i32 - 1);
+    for x in 0..=right {
+        for y in 0..=bottom {
+            let glyph = match (x == 0 || x == right, y == 0 || y == bottom) {
+                (true, true) => '+',
+                (true, false) => '|',
+                (false, true) => '-',
+                (false, false) => ' ',
+            };
+            renderer.put_glyph(
+                pos + Vector::new(x, y),
+                glyph,
+                theme.border,
+                Some(theme.background),
+            );
+        }
+    }
+}
+
+//Takes the place of the map window
+pub fn draw_high_scores(renderer: &mut dyn Renderer, scores: &HighScores, theme: &Theme) {
+    renderer.put_text(Vector::new(1, 1), "High scores", theme.heading);
+    renderer.put_text(
+        Vector::new(1, 3),
+        "Rank  Score  Depth  Turns  Kills  Pebbles",
+        theme.border,
+    );
+    let mut y = 4;
+    for (rank, run) in scores.best(10).iter().enumerate() {
+        let line = format!(
+            "{:>4} {:>6} {:>6} {:>6} {:>6} {:>8}",
+            rank + 1,
+            run.score,
+            run.depth,
+            run.turns,
+            run.kills,
+            run.pebbles_collected
         );
+        renderer.put_text(Vector::new(1, y), &line, theme.text);
+        y += 1;
+    }
+    if scores.runs.is_empty() {
+        renderer.put_text(Vector::new(1, y), "No finished runs yet.", theme.text);
+        y += 1;
     }
 
-    //Text
-    let pebbles = data.pebbles;
-    let mut y_offset = 2.0;
-    data.text
-        .execute(|ui_text| {
-            for (text_type, text) in ui_text.iter() {
-                match *text_type {
-                    "pebbles" => {
-                        window.draw(
-                            &text.area().translate(Vector::new(
-                                TILE_SIZE.x as f32,
-                                MAP_B_BORDER as f32 + TILE_SIZE.y * y_offset,
-                            )),
-                            Img(&text),
-                        );
-
-                        let pebble_ui = &tileset.get(&'o').unwrap();
-                        for n in 1..=pebbles {
-                            window.draw(
-                                &Rectangle::new(
-                                    Vector::new(
-                                        text.area().size.x + (12 * n) as f32,
-                                        MAP_B_BORDER as f32 + TILE_SIZE.y * y_offset,
-                                    ),
-                                    pebble_ui.area().size(),
-                                ),
-                                Blended(&pebble_ui, Palette::WHITE),
-                            );
-                        }
-                        y_offset += 2.0;
-                    }
-                    "message_log" => {
-                        window.draw(
-                            &text.area().translate(Vector::new(
-                                TILE_SIZE.x as f32,
-                                MAP_B_BORDER as f32 + TILE_SIZE.y * y_offset,
-                            )),
-                            Img(&text),
-                        );
-                        y_offset += 2.0;
-                    }
-                    _ => {}
-                }
-            }
-
-            let mut log_offset = y_offset + 2.0;
-            message_log
-                .log
-                .iter()
-                .enumerate()
-                .rev()
-                .for_each(|(_i, message)| {
-                    let text = ui_text.get(message).unwrap();
-                    window.draw(
-                        &text.area().translate(Vector::new(
-                            TILE_SIZE.x as f32,
-                            MAP_B_BORDER as f32 + 10.0 * log_offset,
-                        )),
-                        Img(&text),
-                    );
-                    log_offset += 1.0;
-                });
-
-            Ok(())
-        })
-        .unwrap();
+    renderer.put_text(Vector::new(1, y + 1), "Recent runs", theme.heading);
+    y += 3;
+    for run in scores.recent(10) {
+        let outcome = if run.won {
+            String::from("escaped the cave")
+        } else {
+            format!("killed by {}", run.cause)
+        };
+        let line = format!("{:>6}  depth {}, {}", run.score, run.depth, outcome);
+        renderer.put_text(Vector::new(1, y), &line, theme.text);
+        y += 1;
+    }
+
+    renderer.put_text(
+        Vector::new(1, y + 1),
+        "Press Escape to go back.",
+        theme.border,
+    );
 }
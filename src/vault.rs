@@ -0,0 +1,93 @@
+//Hand-made rooms stamped into generated caves, with their gates wired to levers and plates
+use crate::data;
+use std::io;
+
+//Compiled in rather than read at runtime, since a different vault would change the cave a seed makes
+const VAULTS: &str = include_str!("../static/vaults.txt");
+
+//Every glyph a vault row may use
+const VAULT_GLYPHS: &str = "#.:~=;%I\"*+X_";
+
+pub struct Vault {
+    pub name: String,
+    pub rows: Vec<Vec<char>>,
+}
+
+impl Vault {
+    pub fn width(&self) -> usize {
+        self.rows.first().map_or(0, |row| row.len())
+    }
+
+    pub fn height(&self) -> usize {
+        self.rows.len()
+    }
+}
+
+//"name <text>" starts a vault, the rows follow, // for comments
+pub fn parse(text: &str) -> io::Result<Vec<Vault>> {
+    let invalid = |line: &str| data::invalid_line("vault", line);
+
+    let mut vaults: Vec<Vault> = vec![];
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with("//") {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix("name ") {
+            vaults.push(Vault {
+                name: name.trim().to_string(),
+                rows: vec![],
+            });
+            continue;
+        }
+        let vault = vaults.last_mut().ok_or_else(|| invalid(line))?;
+        let row: Vec<char> = line.chars().collect();
+        if row.iter().any(|glyph| !VAULT_GLYPHS.contains(*glyph))
+            || (vault.height() > 0 && row.len() != vault.width())
+        {
+            return Err(invalid(line));
+        }
+        vault.rows.push(row);
+    }
+    Ok(vaults)
+}
+
+pub fn bundled() -> Vec<Vault> {
+    parse(VAULTS).expect("The bundled vaults could not be parsed.")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bundled_vaults_parse() {
+        let vaults = bundled();
+        assert!(!vaults.is_empty());
+        assert!(vaults.iter().all(|vault| vault.height() > 0));
+    }
+
+    #[test]
+    fn rows_make_up_the_vault() {
+        let vaults = parse("//A comment\nname Cell\n###\n#X+\n###").unwrap();
+        assert_eq!(vaults.len(), 1);
+        assert_eq!(vaults[0].name, "Cell");
+        assert_eq!((vaults[0].width(), vaults[0].height()), (3, 3));
+        assert_eq!(vaults[0].rows[1], vec!['#', 'X', '+']);
+    }
+
+    #[test]
+    fn unknown_glyph_fails() {
+        assert!(parse("name Cell\n#?#").is_err());
+    }
+
+    #[test]
+    fn ragged_row_fails() {
+        assert!(parse("name Cell\n###\n##").is_err());
+    }
+
+    #[test]
+    fn row_without_a_name_fails() {
+        assert!(parse("###").is_err());
+    }
+}
@@ -0,0 +1,165 @@
+//Snapshots of generated caves and screens, compared against tests/golden/*.txt
+//Run with UPDATE_GOLDEN=1 to regenerate the files after an intended change
+use quicksilver::geom::Vector;
+use rl_2019::entities::{self, Entity};
+use rl_2019::game::Game;
+use rl_2019::map::{self, map_size};
+use rl_2019::morgue;
+use rl_2019::progress::PERKS;
+use rl_2019::render::{GridRenderer, SCREEN_SIZE};
+use rl_2019::replay::Action;
+use rl_2019::theme::Theme;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+const SEEDS: [u64; 3] = [1, 42, 2019];
+
+//Pebbles the walk starts with, so its throws really fly
+const WALK_PEBBLES: u32 = 2;
+
+const WALK: [Action; 16] = [
+    Action::Move(1, 0),
+    Action::Move(1, 0),
+    Action::Move(0, 1),
+    Action::Move(0, 1),
+    Action::Move(-1, 0),
+    Action::Move(0, -1),
+    Action::Throw,
+    Action::Move(0, -1),
+    Action::Move(0, -1),
+    Action::Fire,
+    Action::Throw,
+    Action::Move(1, 0),
+    Action::Cancel,
+    Action::Move(-1, 0),
+    Action::Move(-1, 0),
+    Action::Wait,
+];
+
+fn assert_golden(name: &str, actual: &str) {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("golden")
+        .join(format!("{}.txt", name));
+
+    if env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, actual).unwrap();
+        return;
+    }
+
+    let expected = fs::read_to_string(&path).unwrap_or_else(|_| {
+        panic!(
+            "Missing {}, run with UPDATE_GOLDEN=1 to create it",
+            path.display()
+        )
+    });
+    if expected != actual {
+        let line = expected
+            .lines()
+            .zip(actual.lines())
+            .position(|(expected, actual)| expected != actual)
+            .unwrap_or_else(|| expected.lines().count().min(actual.lines().count()));
+        panic!(
+            "{} differs from {} at line {}:\nexpected: {:?}\nactual:   {:?}\n\nactual output:\n{}",
+            name,
+            path.display(),
+            line + 1,
+            expected.lines().nth(line).unwrap_or(""),
+            actual.lines().nth(line).unwrap_or(""),
+            actual
+        );
+    }
+}
+
+//Whole cave with its entities, not just the part the camera shows
+fn map_text(game: &mut Game) -> String {
+    let size = map_size(&game.map);
+    game.set_viewport(size);
+    let mut grid = GridRenderer::new(size);
+    let theme = Theme::new();
+    map::draw_map(&mut grid, &game.map, &game.camera, &theme);
+    entities::draw_entities(&mut grid, &game.entities, &game.camera, &theme);
+    grid.to_text()
+}
+
+fn fov_text(game: &Game) -> String {
+    let size = map_size(&game.map);
+    let mut text = String::new();
+    for y in 0..size.y as usize {
+        let line: String = (0..size.x as usize)
+            .map(|x| if game.map[x][y].explored { '*' } else { ' ' })
+            .collect();
+        text.push_str(line.trim_end());
+        text.push('\n');
+    }
+    text
+}
+
+fn screen_text(game: &Game) -> String {
+    let mut grid = GridRenderer::new(SCREEN_SIZE);
+    game.draw(&mut grid, SCREEN_SIZE);
+    grid.to_text()
+}
+
+#[test]
+fn generated_maps() {
+    for seed in SEEDS.iter() {
+        let mut game = Game::new(*seed);
+        assert_golden(&format!("map_{}", seed), &map_text(&mut game));
+    }
+}
+
+//Picks the first perk whenever the walk levels up, like a player has to
+fn walk(game: &mut Game) {
+    game.ui_data.pebbles = WALK_PEBBLES;
+    for action in WALK.iter() {
+        game.apply(*action);
+        if game.is_choosing_perk() {
+            game.apply(Action::Perk(PERKS[0]));
+        }
+    }
+}
+
+#[test]
+fn starting_screen() {
+    let game = Game::new(SEEDS[0]);
+    assert_golden("screen_start", &screen_text(&game));
+}
+
+#[test]
+fn fov_and_screen_after_walking() {
+    let mut game = Game::new(SEEDS[0]);
+    walk(&mut game);
+    assert_golden("screen_walk", &screen_text(&game));
+    assert_golden("fov_walk", &fov_text(&game));
+}
+
+//A crawler is put right next to the weakened player, then they wait for it
+#[test]
+fn morgue_after_dying() {
+    let mut game = Game::new(SEEDS[0]);
+    walk(&mut game);
+    let player_key = game.player_key;
+    game.entities[player_key].hp = 1;
+    let player_pos = game.player_pos();
+    let next_to = [(1, 0), (-1, 0), (0, 1), (0, -1)]
+        .iter()
+        .map(|(dx, dy)| player_pos + Vector::new(*dx, *dy))
+        .find(|pos| {
+            map::is_walkable(&game.map, *pos)
+                && entities::blocking_at(&game.entities, *pos).is_none()
+        })
+        .unwrap();
+    game.entities
+        .insert_with_key(|k| Entity::spawn("crawler", k, next_to).unwrap());
+    for _ in 0..20 {
+        if game.is_dead() {
+            break;
+        }
+        game.apply(Action::Wait);
+    }
+    assert!(game.is_dead());
+    assert_golden("morgue_death", &morgue::dump(&game));
+}